@@ -0,0 +1,240 @@
+use notify_rust::Notification;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use crate::critical::{ActionRunner, CriticalAction};
+
+/// How long "Snooze" silences battery notifications for.
+pub const SNOOZE: Duration = Duration::from_secs(10 * 60);
+
+/// A button on a critical battery notification.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum NotifAction {
+    Hibernate,
+    Suspend,
+    Snooze,
+}
+
+impl NotifAction {
+    pub const ALL: [NotifAction; 3] = [Self::Hibernate, Self::Suspend, Self::Snooze];
+
+    /// The key the notification server reports back when the action is invoked.
+    pub fn id(&self) -> &'static str {
+        match self {
+            NotifAction::Hibernate => "hibernate",
+            NotifAction::Suspend => "suspend",
+            NotifAction::Snooze => "snooze",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NotifAction::Hibernate => "Hibernate now",
+            NotifAction::Suspend => "Suspend",
+            NotifAction::Snooze => "Snooze 10 min",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.id() == id)
+    }
+
+    /// The system action to run, if this isn't a snooze.
+    pub fn critical_action(&self) -> Option<CriticalAction> {
+        match self {
+            NotifAction::Hibernate => Some(CriticalAction::Hibernate),
+            NotifAction::Suspend => Some(CriticalAction::Suspend),
+            NotifAction::Snooze => None,
+        }
+    }
+}
+
+/// Shows `notif` with every [NotifAction] on another thread, and waits there for the user to pick one.
+///
+/// Hibernate and suspend are run right away with `runner`; then the picked action, or `None` if the notification was dismissed or failed to show, is sent on `done`.
+pub fn show(
+    mut notif: Notification,
+    mut runner: impl ActionRunner + Send + 'static,
+    done: Sender<Option<NotifAction>>,
+) {
+    for action in NotifAction::ALL {
+        notif.action(action.id(), action.label());
+    }
+    thread::spawn(move || {
+        let handle = match notif.show() {
+            Ok(handle) => handle,
+            Err(e) => {
+                log::warn!("Failed to show notification: {:?}", e);
+                let _ = done.send(None);
+                return;
+            }
+        };
+        let mut picked = None;
+        handle.wait_for_action(|id| picked = NotifAction::from_id(id));
+        log::debug!("Notification action: {:?}", picked);
+        if let Some(action) = picked.as_ref().and_then(NotifAction::critical_action) {
+            if let Err(e) = runner.run(&action) {
+                log::error!("Failed to {}: {}", action, e);
+            }
+        }
+        let _ = done.send(picked);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ENV;
+    use dbus::{arg::PropMap, channel::Sender as _, Message};
+    use dbus_crossroads::{Crossroads, IfaceBuilder};
+    use script_lib::dbus_mock::Bus;
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, PoisonError,
+    };
+
+    const PATH: &str = "/org/freedesktop/Notifications";
+    const IFACE: &str = "org.freedesktop.Notifications";
+
+    /// Actions of each notification shown, by id.
+    type Shown = Arc<Mutex<HashMap<u32, Vec<String>>>>;
+
+    /// Serves a fake `org.freedesktop.Notifications` on its own thread.
+    fn serve(bus: &Bus) -> Shown {
+        let shown = Shown::default();
+        let mut cr = Crossroads::new();
+        let iface = cr.register(IFACE, {
+            let shown = Arc::clone(&shown);
+            move |b: &mut IfaceBuilder<()>| {
+                let shown = Arc::clone(&shown);
+                b.method(
+                    "Notify",
+                    (
+                        "app_name",
+                        "replaces_id",
+                        "app_icon",
+                        "summary",
+                        "body",
+                        "actions",
+                        "hints",
+                        "expire_timeout",
+                    ),
+                    ("id",),
+                    move |_,
+                          _,
+                          (_, _, _, _, _, actions, _, _): (
+                        String,
+                        u32,
+                        String,
+                        String,
+                        String,
+                        Vec<String>,
+                        PropMap,
+                        i32,
+                    )| {
+                        let mut shown = shown.lock().unwrap();
+                        let id = shown.len() as u32 + 1;
+                        shown.insert(id, actions);
+                        Ok((id,))
+                    },
+                );
+            }
+        });
+        cr.insert(PATH, &[iface], ());
+        bus.serve(IFACE, cr).unwrap();
+        shown
+    }
+
+    #[derive(Clone, Default)]
+    struct FakeRunner(Arc<Mutex<Vec<CriticalAction>>>);
+
+    impl ActionRunner for FakeRunner {
+        fn run(&mut self, action: &CriticalAction) -> io::Result<()> {
+            self.0.lock().unwrap().push(action.clone());
+            Ok(())
+        }
+    }
+
+    /// Shows a notification, waits for the server to get it, then sends `signal` for it until the action comes back.
+    fn invoke(
+        bus: &Bus,
+        shown: &Shown,
+        runner: &FakeRunner,
+        signal: impl Fn(u32) -> Message,
+    ) -> Option<NotifAction> {
+        let (tx, rx) = mpsc::channel();
+        let before = shown.lock().unwrap().len() as u32;
+        show(Notification::new(), runner.clone(), tx);
+        let id = before + 1;
+        while !shown.lock().unwrap().contains_key(&id) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // the client only subscribes once Notify returns, so a first signal may go unseen
+        let conn = bus.connect().unwrap();
+        for _ in 0..100 {
+            conn.send(signal(id)).unwrap();
+            match rx.recv_timeout(Duration::from_millis(50)) {
+                Err(RecvTimeoutError::Timeout) => {}
+                res => return res.unwrap(),
+            }
+        }
+        panic!("no action after 5s");
+    }
+
+    fn action_invoked(id: u32, action: &str) -> Message {
+        Message::new_signal(PATH, IFACE, "ActionInvoked")
+            .unwrap()
+            .append2(id, action)
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn actions_through_notification_server() {
+        let bus = Bus::start().expect("failed to start dbus-daemon");
+        // notify-rust always connects to the session bus
+        let _env = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        std::env::set_var("DBUS_SESSION_BUS_ADDRESS", bus.address());
+        let shown = serve(&bus);
+        let runner = FakeRunner::default();
+
+        let picked = invoke(&bus, &shown, &runner, |id| action_invoked(id, "hibernate"));
+        assert_eq!(picked, Some(NotifAction::Hibernate));
+        assert_eq!(
+            shown.lock().unwrap()[&1],
+            [
+                "hibernate",
+                "Hibernate now",
+                "suspend",
+                "Suspend",
+                "snooze",
+                "Snooze 10 min"
+            ]
+        );
+        assert_eq!(*runner.0.lock().unwrap(), [CriticalAction::Hibernate]);
+
+        let picked = invoke(&bus, &shown, &runner, |id| action_invoked(id, "snooze"));
+        assert_eq!(picked, Some(NotifAction::Snooze));
+        assert_eq!(runner.0.lock().unwrap().len(), 1);
+
+        let picked = invoke(&bus, &shown, &runner, |id| {
+            Message::new_signal(PATH, IFACE, "NotificationClosed")
+                .unwrap()
+                .append2(id, 2u32)
+        });
+        assert_eq!(picked, None);
+        assert_eq!(runner.0.lock().unwrap().len(), 1);
+        std::env::remove_var("DBUS_SESSION_BUS_ADDRESS");
+    }
+
+    #[test]
+    fn ids() {
+        for action in NotifAction::ALL {
+            assert_eq!(NotifAction::from_id(action.id()), Some(action));
+        }
+        assert_eq!(NotifAction::from_id("__closed"), None);
+        assert_eq!(NotifAction::Snooze.critical_action(), None);
+    }
+}
@@ -0,0 +1,405 @@
+use clap::{parser::ValueSource, ArgMatches};
+use directories::BaseDirs;
+use script_lib::battery::BackendKind;
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error as ThisError;
+
+use crate::critical::CriticalAction;
+use crate::output::OutputFormat;
+use crate::transition::TransitionSetting;
+use crate::warning::WarnLevel;
+use crate::{history, Args};
+
+#[derive(Debug, ThisError)]
+pub enum ConfigError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// A value read from a string with its [FromStr] impl, the same way clap parses the matching flag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parsed<T>(pub T);
+
+impl<'de, T> Deserialize<'de> for Parsed<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map(Parsed).map_err(de::Error::custom)
+    }
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    history::parse_duration(&s)
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+/// Settings for one battery, under `[battery.NAME]`; these replace the global ones for that battery.
+///
+/// Peripherals take their warning levels from here too, by name.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BatteryConfig {
+    /// Shown in notifications instead of the battery's name
+    pub name: Option<String>,
+    pub warn_min: Option<f32>,
+    pub warn_levels: Option<Vec<Parsed<WarnLevel>>>,
+    pub charge_limit: Option<u8>,
+    pub full_threshold: Option<f32>,
+}
+
+impl BatteryConfig {
+    /// The warning levels for this battery, if they're overridden, in the same way as [Args::warn_levels].
+    pub fn warn_levels(&self) -> Option<Vec<WarnLevel>> {
+        match (&self.warn_levels, self.warn_min) {
+            (Some(levels), _) if !levels.is_empty() => {
+                Some(levels.iter().map(|l| l.0.clone()).collect())
+            }
+            (_, Some(percent)) => Some(vec![WarnLevel::at(percent)]),
+            _ => None,
+        }
+    }
+}
+
+/// The config file; each key is named after the flag it stands in for.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub backend: Option<Parsed<BackendKind>>,
+    pub notif_lvl: Option<Parsed<log::LevelFilter>>,
+    pub warn_min: Option<f32>,
+    pub warn_levels: Option<Vec<Parsed<WarnLevel>>>,
+    pub warn_hysteresis: Option<f32>,
+    pub charge_limit: Option<u8>,
+    pub full_threshold: Option<f32>,
+    pub transitions: Vec<Parsed<TransitionSetting>>,
+    pub peripherals: Option<bool>,
+    pub stop_min: Option<f32>,
+    pub stop_action: Option<Parsed<CriticalAction>>,
+    pub stop_command: Option<String>,
+    #[serde(deserialize_with = "duration")]
+    pub stop_grace: Option<Duration>,
+    pub format: Option<Parsed<OutputFormat>>,
+    pub metrics: Option<PathBuf>,
+    /// Batteries to check if none are given on the command line
+    pub batteries: Vec<String>,
+    /// Batteries and peripherals to leave out when checking everything
+    pub ignore: Vec<String>,
+    pub battery: HashMap<String, BatteryConfig>,
+}
+
+/// $XDG_CONFIG_HOME/check-battery/config.toml
+pub fn default_path() -> Option<PathBuf> {
+    Some(
+        BaseDirs::new()?
+            .config_dir()
+            .join("check-battery")
+            .join("config.toml"),
+    )
+}
+
+fn check_percent(key: &str, percent: f32) -> Result<(), ConfigError> {
+    match (0.0..=100.0).contains(&percent) {
+        true => Ok(()),
+        false => Err(ConfigError::Invalid(format!(
+            "{key}: not a percentage: {percent}"
+        ))),
+    }
+}
+
+fn check_limit(key: &str, limit: Option<u8>, threshold: Option<f32>) -> Result<(), ConfigError> {
+    if let Some(limit) = limit {
+        check_percent(&format!("{key}charge-limit"), limit as f32)?;
+    }
+    match threshold {
+        Some(t) if !(0.0..=1.0).contains(&t) => Err(ConfigError::Invalid(format!(
+            "{key}full-threshold: not a fraction: {t}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn check_levels(
+    key: &str,
+    warn_min: Option<f32>,
+    levels: Option<&[Parsed<WarnLevel>]>,
+) -> Result<(), ConfigError> {
+    if let Some(percent) = warn_min {
+        check_percent(&format!("{key}warn-min"), percent)?;
+    }
+    for level in levels.unwrap_or_default() {
+        check_percent(&format!("{key}warn-levels"), level.0.percent)?;
+    }
+    Ok(())
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: Config = toml::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl Config {
+    /// Reads the config file at `path`; a missing file is only an error if `required`.
+    pub fn load(path: &Path, required: bool) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(s) => s.parse(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Catches what deserializing can't: out of range values and conflicting keys.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_levels("", self.warn_min, self.warn_levels.as_deref())?;
+        check_limit("", self.charge_limit, self.full_threshold)?;
+        if let Some(percent) = self.stop_min {
+            check_percent("stop-min", percent)?;
+        }
+        if self.warn_hysteresis.is_some_and(|h| h < 0.0) {
+            return Err(ConfigError::Invalid(
+                "warn-hysteresis: must not be negative".to_owned(),
+            ));
+        }
+        if self.stop_action.is_some() && self.stop_command.is_some() {
+            return Err(ConfigError::Invalid(
+                "stop-action and stop-command can't both be set".to_owned(),
+            ));
+        }
+        for (name, bat) in &self.battery {
+            let key = format!("battery.{name}.");
+            check_levels(&key, bat.warn_min, bat.warn_levels.as_deref())?;
+            check_limit(&key, bat.charge_limit, bat.full_threshold)?;
+        }
+        Ok(())
+    }
+
+    /// Fills in every setting of `args` that wasn't given on the command line or in the environment.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let unset = |id: &str| {
+            matches
+                .value_source(id)
+                .map_or(true, |src| src == ValueSource::DefaultValue)
+        };
+        if let (Some(backend), true) = (self.backend, unset("backend")) {
+            args.backend = backend.0;
+        }
+        if let (Some(notif_lvl), true) = (self.notif_lvl, unset("notif-lvl")) {
+            args.notif_lvl = Some(notif_lvl.0);
+        }
+        let levels_unset = unset("warn-min") && unset("warn-level");
+        if let (Some(warn_min), true) = (self.warn_min, levels_unset) {
+            args.warn_min = warn_min;
+        }
+        if let (Some(levels), true) = (self.warn_levels, levels_unset) {
+            args.warn_level = levels.into_iter().map(|l| l.0).collect();
+        }
+        if let (Some(hysteresis), true) = (self.warn_hysteresis, unset("warn-hysteresis")) {
+            args.warn_hysteresis = hysteresis;
+        }
+        if let (Some(limit), true) = (self.charge_limit, unset("charge-limit")) {
+            args.charge_limit = Some(limit);
+        }
+        if let (Some(threshold), true) = (self.full_threshold, unset("full-threshold")) {
+            args.full_threshold = threshold;
+        }
+        // --transition only overrides the transitions it names
+        args.transition = self
+            .transitions
+            .into_iter()
+            .map(|t| t.0)
+            .chain(std::mem::take(&mut args.transition))
+            .collect();
+        // a bare flag can't be unset from the command line, so the file can only turn it on
+        args.peripherals |= self.peripherals.unwrap_or_default();
+        if let (Some(stop_min), true) = (self.stop_min, unset("stop-min")) {
+            args.stop_min = Some(stop_min);
+        }
+        let action_unset = unset("stop-action") && unset("stop-command");
+        if let (Some(action), true) = (self.stop_action, action_unset) {
+            args.stop_action = action.0;
+        }
+        if let (Some(cmd), true) = (self.stop_command, action_unset) {
+            args.stop_command = Some(cmd);
+        }
+        if let (Some(grace), true) = (self.stop_grace, unset("stop-grace")) {
+            args.stop_grace = grace;
+        }
+        if let (Some(format), true) = (self.format, unset("format")) {
+            args.format = format.0;
+        }
+        if let (Some(metrics), true) = (self.metrics, unset("metrics")) {
+            args.metrics = Some(metrics);
+        }
+        if args.batteries.is_empty() {
+            args.batteries = self.batteries;
+        }
+        args.ignore = self.ignore;
+        args.overrides = self
+            .battery
+            .into_iter()
+            .map(|(name, mut bat)| {
+                if !levels_unset {
+                    bat.warn_min = None;
+                    bat.warn_levels = None;
+                }
+                if !unset("charge-limit") {
+                    bat.charge_limit = None;
+                }
+                if !unset("full-threshold") {
+                    bat.full_threshold = None;
+                }
+                (name, bat)
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ENV;
+    use crate::transition::{self, Transition};
+    use clap::{CommandFactory, FromArgMatches};
+    use notify_rust::Urgency;
+    use std::sync::PoisonError;
+
+    const EXAMPLE: &str = r#"
+notif-lvl = "Info"
+warn-levels = ["20:normal", "10:critical:Battery critical"]
+stop-min = 5
+stop-action = "suspend"
+stop-grace = "2m"
+format = "waybar"
+metrics = "/run/node-exporter/battery.prom"
+transitions = ["charging:off", "unplugged:critical"]
+ignore = ["hidpp_battery_0"]
+
+[battery.BAT1]
+name = "Slice battery"
+warn-min = 30
+charge-limit = 60
+"#;
+
+    /// Parses `argv` with the `env` variables set, then applies `config`.
+    fn parse_args(argv: &[&str], env: &[(&str, &str)], config: &str) -> Args {
+        let _env = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        for (key, val) in env {
+            std::env::set_var(key, val);
+        }
+        let matches = Args::command()
+            .try_get_matches_from(std::iter::once("check-battery").chain(argv.iter().copied()));
+        for (key, _) in env {
+            std::env::remove_var(key);
+        }
+        let matches = matches.unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        config.parse::<Config>().unwrap().apply(&mut args, &matches);
+        args
+    }
+
+    #[test]
+    fn parse() {
+        let config: Config = EXAMPLE.parse().unwrap();
+        assert_eq!(config.notif_lvl, Some(Parsed(log::LevelFilter::Info)));
+        assert_eq!(config.stop_action, Some(Parsed(CriticalAction::Suspend)));
+        assert_eq!(config.stop_grace, Some(Duration::from_secs(120)));
+        let bat1 = &config.battery["BAT1"];
+        assert_eq!(bat1.name.as_deref(), Some("Slice battery"));
+        assert_eq!(bat1.warn_levels(), Some(vec![WarnLevel::at(30.0)]));
+        assert_eq!(config.battery.get("BAT0"), None);
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        for bad in [
+            "warn-min = 120",
+            "warn-mni = 10",
+            "stop-action = \"explode\"",
+            "stop-grace = \"soon\"",
+            "warn-levels = [\"10:loud\"]",
+            "transitions = [\"charging:loud\"]",
+            "stop-action = \"suspend\"\nstop-command = \"true\"",
+            "[battery.BAT0]\nfull-threshold = 95",
+            "[battery.BAT0]\nwarn-levels = [\"-5\"]",
+        ] {
+            assert!(bad.parse::<Config>().is_err(), "{bad}");
+        }
+        assert!(Config::load(Path::new("/nonexistent/config.toml"), false).is_ok());
+        assert!(Config::load(Path::new("/nonexistent/config.toml"), true).is_err());
+    }
+
+    #[test]
+    fn fills_in_unset_args() {
+        let args = parse_args(&[], &[], EXAMPLE);
+        assert_eq!(args.notif_lvl, Some(log::LevelFilter::Info));
+        assert_eq!(args.warn_levels().len(), 2);
+        assert_eq!(args.warn_levels()[1].urgency, Urgency::Critical);
+        assert_eq!(args.stop_min, Some(5.0));
+        assert_eq!(args.stop_action, CriticalAction::Suspend);
+        assert_eq!(args.format, OutputFormat::Waybar);
+        assert_eq!(
+            args.metrics.as_deref(),
+            Some(Path::new("/run/node-exporter/battery.prom"))
+        );
+        assert_eq!(args.ignore, ["hidpp_battery_0"]);
+        assert_eq!(args.overrides["BAT1"].charge_limit, Some(60));
+    }
+
+    #[test]
+    fn command_line_and_env_take_precedence() {
+        let args = parse_args(
+            &[
+                "-w",
+                "15",
+                "--stop-command",
+                "true",
+                "--charge-limit",
+                "80",
+                "--transition",
+                "charging",
+            ],
+            &[("CHECK_BATTERY_STOP_MIN", "3")],
+            EXAMPLE,
+        );
+        assert_eq!(args.stop_min, Some(3.0));
+        // --warn-min replaces the file's levels, including per-battery ones
+        assert_eq!(args.warn_levels(), [WarnLevel::at(15.0)]);
+        assert_eq!(args.overrides["BAT1"].warn_levels(), None);
+        assert_eq!(args.overrides["BAT1"].charge_limit, None);
+        assert_eq!(
+            args.overrides["BAT1"].name.as_deref(),
+            Some("Slice battery")
+        );
+        assert_eq!(args.stop_command.as_deref(), Some("true"));
+        assert_eq!(
+            transition::urgency(&args.transition, Transition::Charging),
+            Some(Urgency::Low)
+        );
+        assert_eq!(
+            transition::urgency(&args.transition, Transition::Unplugged),
+            Some(Urgency::Critical)
+        );
+        assert_eq!(args.stop_action, CriticalAction::Hibernate);
+        assert_eq!(args.notif_lvl, Some(log::LevelFilter::Info));
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt::Display;
+use std::io;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// What to do once the combined battery level drops below --stop-min.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum CriticalAction {
+    Hibernate,
+    Suspend,
+    HybridSleep,
+    PowerOff,
+    /// Run through `sh -c`
+    Custom(String),
+}
+
+impl Display for CriticalAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CriticalAction::Hibernate => f.write_str("hibernate"),
+            CriticalAction::Suspend => f.write_str("suspend"),
+            CriticalAction::HybridSleep => f.write_str("hybrid-sleep"),
+            CriticalAction::PowerOff => f.write_str("poweroff"),
+            CriticalAction::Custom(cmd) => write!(f, "custom ({cmd})"),
+        }
+    }
+}
+
+/// Parses one of the systemctl verbs; custom commands are given separately.
+impl FromStr for CriticalAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hibernate" => Ok(Self::Hibernate),
+            "suspend" => Ok(Self::Suspend),
+            "hybrid-sleep" => Ok(Self::HybridSleep),
+            "poweroff" => Ok(Self::PowerOff),
+            _ => Err(format!("unrecognized critical action: {s}")),
+        }
+    }
+}
+
+impl CriticalAction {
+    /// Present participle for notifications, e.g. "hibernating".
+    pub fn verb(&self) -> &'static str {
+        match self {
+            CriticalAction::Hibernate => "hibernating",
+            CriticalAction::Suspend => "suspending",
+            CriticalAction::HybridSleep => "suspending",
+            CriticalAction::PowerOff => "powering off",
+            CriticalAction::Custom(_) => "running critical action",
+        }
+    }
+
+    pub fn command(&self) -> Command {
+        let mut cmd;
+        match self {
+            CriticalAction::Custom(custom) => {
+                cmd = Command::new("sh");
+                cmd.arg("-c").arg(custom);
+            }
+            systemctl => {
+                cmd = Command::new("systemctl");
+                cmd.arg(systemctl.to_string());
+            }
+        }
+        cmd
+    }
+}
+
+/// Carries out a [CriticalAction]; replaced in tests so they don't suspend the machine.
+pub trait ActionRunner {
+    fn run(&mut self, action: &CriticalAction) -> io::Result<()>;
+}
+
+/// Runs the action's [CriticalAction::command] and waits for it to finish.
+#[derive(Debug, Default)]
+pub struct SystemRunner;
+
+impl ActionRunner for SystemRunner {
+    fn run(&mut self, action: &CriticalAction) -> io::Result<()> {
+        let status = action.command().status()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!("{action} exited with {status}"))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Ran,
+    /// External power came back during the grace period
+    Cancelled,
+    Failed(io::Error),
+}
+
+/// Waits out `grace` in steps of at most `step`, then runs `action` unless the machine was plugged in meanwhile.
+///
+/// `wait` is called before each step with the time remaining and the length of the step, and is expected to notify the user and sleep for the step.
+pub fn countdown(
+    action: &CriticalAction,
+    grace: Duration,
+    step: Duration,
+    runner: &mut dyn ActionRunner,
+    mut plugged_in: impl FnMut() -> bool,
+    mut wait: impl FnMut(Duration, Duration),
+) -> Outcome {
+    let mut remaining = grace;
+    loop {
+        if plugged_in() {
+            return Outcome::Cancelled;
+        }
+        if remaining.is_zero() {
+            break;
+        }
+        let step = remaining.min(step);
+        wait(remaining, step);
+        remaining -= step;
+    }
+    match runner.run(action) {
+        Ok(()) => Outcome::Ran,
+        Err(e) => Outcome::Failed(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeRunner {
+        ran: Vec<CriticalAction>,
+        fail: bool,
+    }
+
+    impl ActionRunner for FakeRunner {
+        fn run(&mut self, action: &CriticalAction) -> io::Result<()> {
+            self.ran.push(action.clone());
+            match self.fail {
+                true => Err(io::Error::other("failed")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn runs_after_grace_period() {
+        let mut runner = FakeRunner::default();
+        let mut waits = Vec::new();
+        let outcome = countdown(
+            &CriticalAction::Hibernate,
+            secs(25),
+            secs(10),
+            &mut runner,
+            || false,
+            |remaining, step| waits.push((remaining.as_secs(), step.as_secs())),
+        );
+        assert!(matches!(outcome, Outcome::Ran));
+        assert_eq!(waits, [(25, 10), (15, 10), (5, 5)]);
+        assert_eq!(runner.ran, [CriticalAction::Hibernate]);
+    }
+
+    #[test]
+    fn cancelled_when_plugged_in() {
+        let mut runner = FakeRunner::default();
+        let mut checks = 0;
+        let outcome = countdown(
+            &CriticalAction::Suspend,
+            secs(60),
+            secs(10),
+            &mut runner,
+            || {
+                checks += 1;
+                checks > 2
+            },
+            |_, _| {},
+        );
+        assert!(matches!(outcome, Outcome::Cancelled));
+        assert!(runner.ran.is_empty());
+    }
+
+    #[test]
+    fn no_grace_period() {
+        let mut runner = FakeRunner {
+            fail: true,
+            ..Default::default()
+        };
+        let outcome = countdown(
+            &CriticalAction::Custom("true".to_owned()),
+            Duration::ZERO,
+            secs(10),
+            &mut runner,
+            || false,
+            |_, _| panic!("nothing to wait for"),
+        );
+        assert!(matches!(outcome, Outcome::Failed(_)));
+        assert_eq!(runner.ran.len(), 1);
+    }
+
+    #[test]
+    fn commands() {
+        let cmd = CriticalAction::HybridSleep.command();
+        assert_eq!(cmd.get_program(), "systemctl");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["hybrid-sleep"]);
+        let cmd = CriticalAction::Custom("loginctl lock-session && systemctl suspend".to_owned())
+            .command();
+        assert_eq!(cmd.get_program(), "sh");
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            ["-c", "loginctl lock-session && systemctl suspend"]
+        );
+        assert_eq!(
+            CriticalAction::from_str("poweroff"),
+            Ok(CriticalAction::PowerOff)
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use notify_rust::{Hint, Notification, Urgency};
+use script_lib::{
+    battery::{self, Battery, BatteryStatus, FullState, Peripheral},
+    notif::NOTIF_ICON,
+};
+use serde::{Serialize, Serializer};
+
+use crate::transition::{self, Transition, TransitionSetting};
+use crate::warning::WarnLevel;
+
+fn serialize_urgency<S: Serializer>(urgency: &Urgency, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(match urgency {
+        Urgency::Low => "low",
+        Urgency::Normal => "normal",
+        Urgency::Critical => "critical",
+    })
+}
+
+/// A notification decided on, but not sent yet.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notif {
+    /// Notifications with the same tag replace each other
+    pub tag: String,
+    pub category: &'static str,
+    pub summary: String,
+    pub body: String,
+    #[serde(serialize_with = "serialize_urgency")]
+    pub urgency: Urgency,
+    /// Stays up until dismissed
+    pub persistent: bool,
+    /// Offers the [NotifAction](crate::actions::NotifAction)s
+    pub actions: bool,
+    /// Level shown as a progress bar, in percent
+    pub value: Option<i32>,
+}
+
+impl Notif {
+    pub fn to_notification(&self) -> Notification {
+        let mut notif = Notification::new();
+        notif
+            .appname("check-battery")
+            .summary(&self.summary)
+            .body(&self.body)
+            .icon(NOTIF_ICON.to_str().unwrap())
+            .hint(Hint::Category(self.category.to_owned()))
+            .hint(Hint::Custom(
+                "x-dunst-stack-tag".to_owned(),
+                self.tag.clone(),
+            ))
+            .urgency(self.urgency);
+        if let Some(value) = self.value {
+            notif.hint(Hint::CustomInt("value".to_owned(), value));
+        }
+        if self.persistent {
+            notif.timeout(0);
+        }
+        notif
+    }
+}
+
+/// Decides what to notify about `battery`, given its state at the previous check and the warning level it just crossed, if any.
+pub fn battery_notif(
+    battery: &Battery,
+    prev: Option<&Battery>,
+    warn_level: Option<&WarnLevel>,
+    display_name: &str,
+    notif_lvl: log::LevelFilter,
+    full_threshold: f32,
+    transitions: &[TransitionSetting],
+) -> Option<Notif> {
+    let percent = battery.part_actual() * 100.0;
+    let full_state = battery.full_state(full_threshold);
+    let body = match battery.time_to_empty().or_else(|| battery.time_to_full()) {
+        _ if full_state == FullState::HeldAtLimit => format!(
+            "{} (held at {}% limit)",
+            display_name,
+            battery.charge_limit.unwrap_or(100)
+        ),
+        Some(dur) => format!(
+            "{} ({}, {} {})",
+            display_name,
+            battery.status,
+            battery::format_duration(dur),
+            match battery.status {
+                BatteryStatus::Charging => "until full",
+                _ => "left",
+            }
+        ),
+        None => format!("{} ({})", display_name, battery.status),
+    };
+    let mut notif = Notif {
+        tag: battery.name.clone(),
+        category: "system",
+        summary: format!("Battery: {percent:.0}%"),
+        body,
+        urgency: Urgency::Low,
+        persistent: false,
+        actions: false,
+        value: Some(percent as i32),
+    };
+    let transition = prev
+        .and_then(|prev| Transition::between(prev, battery))
+        .filter(|_| notif_lvl >= log::LevelFilter::Info)
+        .and_then(|t| Some((t, transition::urgency(transitions, t)?)));
+    match (warn_level, transition, notif_lvl) {
+        (Some(level), _, _) => {
+            notif.summary = format!("{}: {percent:.0}%", level.text);
+            notif.urgency = level.urgency;
+            notif.persistent = level.urgency == Urgency::Critical;
+            notif.actions = level.urgency == Urgency::Critical;
+        }
+        (_, Some((transition, urgency)), _) => {
+            notif.summary = transition.summary(battery);
+            notif.urgency = urgency;
+        }
+        // reaching the charge limit is a transition of its own
+        (_, _, log::LevelFilter::Info)
+            if full_state == FullState::Full
+                && prev.map_or(true, |prev| prev.full_state(full_threshold) != full_state) => {}
+        (_, _, log::LevelFilter::Trace) => {}
+        _ => return None,
+    }
+    Some(notif)
+}
+
+/// Notification tag of a peripheral, kept apart from the system batteries so one doesn't replace the other.
+pub fn peripheral_tag(name: &str) -> String {
+    format!("peripheral:{name}")
+}
+
+/// Decides whether to notify about a peripheral: when it just crossed a warning level, or always at Trace.
+pub fn peripheral_notif(
+    peripheral: &Peripheral,
+    warn_level: Option<&WarnLevel>,
+    notif_lvl: log::LevelFilter,
+) -> Option<Notif> {
+    let urgency = match (warn_level, notif_lvl) {
+        (Some(level), _) => level.urgency,
+        (None, log::LevelFilter::Trace) => Urgency::Low,
+        _ => return None,
+    };
+    let level = match (peripheral.capacity, peripheral.capacity_level) {
+        (Some(capacity), _) => format!("{capacity}%"),
+        (None, Some(level)) => level.to_string(),
+        (None, None) => peripheral.status.to_string(),
+    };
+    Some(Notif {
+        tag: peripheral_tag(&peripheral.name),
+        category: "device",
+        summary: format!("{}: {}", peripheral.display_name(), level),
+        body: format!("{} ({})", peripheral.name, peripheral.status),
+        urgency,
+        persistent: false,
+        actions: false,
+        value: peripheral.capacity.map(i32::from),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::battery;
+    use log::LevelFilter;
+    use script_lib::battery::CapacityLevel;
+    use BatteryStatus::*;
+
+    const STATUSES: [BatteryStatus; 5] = [Unknown, Discharging, Charging, NotCharging, Full];
+    const LEVELS: [LevelFilter; 3] = [LevelFilter::Warn, LevelFilter::Info, LevelFilter::Trace];
+
+    fn decide(
+        cur: &Battery,
+        prev: Option<&Battery>,
+        warn_level: Option<&WarnLevel>,
+        notif_lvl: LevelFilter,
+    ) -> Option<Notif> {
+        battery_notif(cur, prev, warn_level, "BAT0", notif_lvl, 0.95, &[])
+    }
+
+    #[test]
+    fn warning_levels_always_notify() {
+        let normal: WarnLevel = "20:normal".parse().unwrap();
+        let critical: WarnLevel = "10".parse().unwrap();
+        for status in STATUSES {
+            for notif_lvl in LEVELS {
+                let bat = battery(status, 9);
+                let notif = decide(&bat, None, Some(&normal), notif_lvl).unwrap();
+                assert_eq!(notif.summary, "Battery low: 9%");
+                assert_eq!(notif.urgency, Urgency::Normal);
+                assert!(!notif.persistent && !notif.actions);
+
+                let notif = decide(&bat, Some(&bat), Some(&critical), notif_lvl).unwrap();
+                assert_eq!(notif.urgency, Urgency::Critical);
+                assert!(notif.persistent && notif.actions);
+                assert_eq!(notif.tag, "BAT0");
+                assert_eq!(notif.value, Some(9));
+            }
+        }
+    }
+
+    #[test]
+    fn without_a_warning() {
+        for status in STATUSES {
+            for percent in [50, 99] {
+                let bat = battery(status, percent);
+                let full = bat.full_state(0.95) == FullState::Full;
+                assert_eq!(decide(&bat, None, None, LevelFilter::Warn), None);
+                assert_eq!(
+                    decide(&bat, None, None, LevelFilter::Info).is_some(),
+                    full,
+                    "{status} at {percent}%"
+                );
+                // only the transition into full is notified
+                assert_eq!(decide(&bat, Some(&bat), None, LevelFilter::Info), None);
+                let notif = decide(&bat, Some(&bat), None, LevelFilter::Trace).unwrap();
+                assert_eq!(notif.urgency, Urgency::Low);
+                assert_eq!(notif.summary, format!("Battery: {percent}%"));
+            }
+        }
+        // charging counts as full past the threshold
+        assert!(decide(&battery(Charging, 99), None, None, LevelFilter::Info).is_some());
+        assert!(decide(&battery(Charging, 50), None, None, LevelFilter::Info).is_none());
+        assert!(decide(&battery(NotCharging, 50), None, None, LevelFilter::Info).is_some());
+        // the summary rounds like a warning does
+        let bat = Battery {
+            remaining: 2,
+            actual_max: 3,
+            ..battery(Discharging, 0)
+        };
+        let notif = decide(&bat, None, None, LevelFilter::Trace).unwrap();
+        assert_eq!(notif.summary, "Battery: 67%");
+    }
+
+    #[test]
+    fn transitions() {
+        for prev in STATUSES {
+            for cur in STATUSES {
+                let (prev, cur) = (battery(prev, 50), battery(cur, 50));
+                let transition = Transition::between(&prev, &cur);
+                for notif_lvl in LEVELS {
+                    let notif = decide(&cur, Some(&prev), None, notif_lvl);
+                    match (transition, notif_lvl) {
+                        (_, LevelFilter::Warn) => assert_eq!(notif, None),
+                        (Some(t), _) => {
+                            let notif = notif.unwrap();
+                            assert_eq!(notif.summary, t.summary(&cur));
+                            assert_eq!(notif.urgency, t.default_urgency());
+                        }
+                        (None, LevelFilter::Trace) => assert!(notif.is_some()),
+                        (None, _) => assert_eq!(
+                            notif.is_some(),
+                            cur.full_state(0.95) == FullState::Full
+                                && prev.full_state(0.95) != FullState::Full,
+                        ),
+                    }
+                }
+            }
+        }
+
+        let settings = [
+            "unplugged:critical".parse().unwrap(),
+            "charging:off".parse().unwrap(),
+        ];
+        let notify = |prev, cur| {
+            battery_notif(
+                &battery(cur, 50),
+                Some(&battery(prev, 50)),
+                None,
+                "Slice battery",
+                LevelFilter::Info,
+                0.95,
+                &settings,
+            )
+        };
+        let notif = notify(Charging, Discharging).unwrap();
+        assert_eq!(notif.urgency, Urgency::Critical);
+        assert_eq!(notif.summary, "Unplugged: 50%");
+        assert_eq!(notif.body, "Slice battery (Discharging)");
+        assert_eq!(notify(Discharging, Charging), None);
+    }
+
+    #[test]
+    fn held_at_limit() {
+        let mut bat = battery(NotCharging, 80);
+        bat.charge_limit = Some(80);
+        let mut prev = bat.clone();
+        prev.status = Charging;
+        let notif = decide(&bat, Some(&prev), None, LevelFilter::Info).unwrap();
+        assert_eq!(notif.summary, "Stopped charging at 80% limit");
+        assert_eq!(notif.body, "BAT0 (held at 80% limit)");
+        assert_eq!(decide(&bat, Some(&bat), None, LevelFilter::Info), None);
+        assert_eq!(decide(&bat, None, None, LevelFilter::Info), None);
+    }
+
+    #[test]
+    fn peripherals() {
+        let mut mouse = Peripheral {
+            name: "hidpp_battery_0".to_owned(),
+            status: Discharging,
+            capacity: None,
+            capacity_level: Some(CapacityLevel::Low),
+            manufacturer: Some("Logitech".to_owned()),
+            model_name: Some("MX Master 3".to_owned()),
+            serial_number: None,
+        };
+        let normal: WarnLevel = "20:normal".parse().unwrap();
+        let notif = peripheral_notif(&mouse, Some(&normal), LevelFilter::Warn).unwrap();
+        assert_eq!(notif.summary, "Logitech MX Master 3: Low");
+        assert_eq!(notif.tag, "peripheral:hidpp_battery_0");
+        assert_eq!(notif.urgency, Urgency::Normal);
+        assert!(!notif.persistent && !notif.actions);
+        // once it has fired, a low peripheral isn't notified again
+        assert_eq!(peripheral_notif(&mouse, None, LevelFilter::Info), None);
+        mouse.capacity = Some(60);
+        let notif = peripheral_notif(&mouse, None, LevelFilter::Trace).unwrap();
+        assert_eq!(notif.urgency, Urgency::Low);
+        assert_eq!(notif.value, Some(60));
+    }
+
+    #[test]
+    fn serializes() {
+        let notif = decide(&battery(Full, 100), None, None, LevelFilter::Info).unwrap();
+        assert_eq!(
+            serde_json::to_string(&notif).unwrap(),
+            r#"{"tag":"BAT0","category":"system","summary":"Battery: 100%","body":"BAT0 (Full)","urgency":"low","persistent":false,"actions":false,"value":100}"#
+        );
+    }
+}
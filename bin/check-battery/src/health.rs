@@ -0,0 +1,108 @@
+use script_lib::battery::{Battery, CapacityUnit};
+use std::fmt::Write;
+
+/// Formats a multi-line health report for a battery.
+pub fn report(battery: &Battery) -> String {
+    let mut res = format!("{}:\n", battery.name);
+    let mut field = |name: &str, val: Option<&dyn std::fmt::Display>| {
+        if let Some(val) = val {
+            writeln!(res, "  {name}: {val}").unwrap();
+        }
+    };
+    field("status", Some(&battery.status));
+    field(
+        "manufacturer",
+        battery.manufacturer.as_ref().map(|v| v as _),
+    );
+    field("model", battery.model_name.as_ref().map(|v| v as _));
+    field("serial", battery.serial_number.as_ref().map(|v| v as _));
+    field("technology", battery.technology.as_ref().map(|v| v as _));
+    field("cycles", battery.cycle_count.as_ref().map(|v| v as _));
+    field(
+        "charge limit",
+        battery
+            .charge_limit
+            .map(|v| format!("{v}%"))
+            .as_ref()
+            .map(|v| v as _),
+    );
+    write!(
+        res,
+        "  capacity: {} / {} design",
+        battery.actual_max_capacity(),
+        battery.factory_max_capacity(),
+    )
+    .unwrap();
+    // also show charge-based capacities as energy, which is what most spec sheets list
+    if battery.unit == CapacityUnit::Charge {
+        let voltage = battery.nominal_voltage();
+        if let (Some(actual), Some(factory)) = (
+            battery.actual_max_capacity().watt_hours(voltage),
+            battery.factory_max_capacity().watt_hours(voltage),
+        ) {
+            write!(res, " ({actual:.2}Wh / {factory:.2}Wh design)").unwrap();
+        }
+    }
+    // some firmware reports no design capacity, which leaves nothing to compare against
+    if battery.factory_max > 0 {
+        write!(
+            res,
+            "\n  health: {:.1}% ({:.1}% wear)",
+            battery.health() * 100.0,
+            battery.wear() * 100.0
+        )
+        .unwrap();
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture;
+
+    #[test]
+    fn energy_battery() {
+        assert_eq!(
+            report(&fixture("BAT0")),
+            "BAT0:
+  status: Discharging
+  manufacturer: SMP
+  model: 5B10W13975
+  serial: 1234
+  technology: Li-poly
+  cycles: 312
+  charge limit: 80%
+  capacity: 51.27Wh / 57.00Wh design
+  health: 89.9% (10.1% wear)"
+        );
+    }
+
+    #[test]
+    fn charge_battery() {
+        // with the capacity in Wh too, at the design voltage
+        assert_eq!(
+            report(&fixture("BAT1")),
+            "BAT1:
+  status: Charging
+  manufacturer: SWD-ATL3.618
+  model: DELL 7FHHV
+  serial: 4095
+  technology: Li-ion
+  cycles: 0
+  capacity: 3.96Ah / 4.21Ah design (45.19Wh / 47.99Wh design)
+  health: 94.2% (5.8% wear)"
+        );
+    }
+
+    #[test]
+    fn without_design_capacity() {
+        let mut bat0 = fixture("BAT0");
+        bat0.factory_max = 0;
+        let report = report(&bat0);
+        assert!(
+            report.ends_with("\n  capacity: 51.27Wh / 0.00Wh design"),
+            "{report}"
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use bincode::Options;
+use script_lib::battery::{
+    self,
+    history::{History, Sample},
+    Battery, BatteryStatus, CapacityUnit,
+};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::{state, BINCODE_OPTS};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unrecognized export format: {s}")),
+        }
+    }
+}
+
+/// Parses durations like `90s`, `30m`, `12h` or `7d`; bare numbers are seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (num, mult) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 60 * 60 * 24),
+        _ => (s, 1),
+    };
+    let n = num
+        .parse::<u64>()
+        .map_err(|_e| format!("invalid duration: {s}"))?;
+    n.checked_mul(mult)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration too long: {s}"))
+}
+
+fn history_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(name)
+}
+
+pub fn load(dir: &Path, name: &str) -> History {
+    let path = history_path(dir, name);
+    match fs::read(&path) {
+        Ok(bytes) => BINCODE_OPTS.deserialize(&bytes).unwrap_or_else(|e| {
+            log::error!("Deserialization of {:?}: {:?}", path, e);
+            History::default()
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => History::default(),
+        Err(e) => {
+            log::error!("Failed to read {:?}: {:?}", path, e);
+            History::default()
+        }
+    }
+}
+
+pub fn save(dir: &Path, name: &str, history: &History) {
+    let path = history_path(dir, name);
+    match BINCODE_OPTS.serialize(history) {
+        Ok(bytes) => {
+            if let Err(e) = state::write_atomic(&path, &bytes) {
+                log::error!("Failed to write {:?}: {:?}", path, e);
+            }
+        }
+        Err(e) => log::error!("Serialization of {:?}: {:?}", path, e),
+    }
+}
+
+/// Appends a sample of each battery to its history, dropping samples older than `max_age`.
+pub fn record<'b>(
+    dir: &Path,
+    batteries: impl IntoIterator<Item = &'b Battery>,
+    now: u64,
+    max_age: Duration,
+) {
+    for battery in batteries {
+        // a --daemon process records without holding the state file lock
+        let _lock = match state::lock(&history_path(dir, &battery.name)) {
+            Ok(lock) => lock,
+            Err(e) => {
+                log::error!("Failed to lock history of {}: {:?}", battery.name, e);
+                continue;
+            }
+        };
+        let mut history = load(dir, &battery.name);
+        history.push(Sample::new(battery, now));
+        history.prune(now, max_age);
+        save(dir, &battery.name, &history);
+    }
+}
+
+fn format_rate(rate: Option<f64>, unit: CapacityUnit) -> String {
+    match rate {
+        None => "-".to_owned(),
+        Some(rate) => format!(
+            "{:.2}{}",
+            rate / 1_000_000.0,
+            match unit {
+                CapacityUnit::Energy => "W",
+                CapacityUnit::Charge => "A",
+            }
+        ),
+    }
+}
+
+/// Formats the average charge and discharge rates of a battery over each window.
+pub fn report(name: &str, history: &History, now: u64, windows: &[Duration]) -> String {
+    let mut res = format!("{}:\n", name);
+    let (first, last) = match (history.samples.front(), history.samples.back()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            res.push_str("  no samples");
+            return res;
+        }
+    };
+    writeln!(
+        res,
+        "  samples: {} over {}",
+        history.samples.len(),
+        battery::format_duration(Duration::from_secs(last.timestamp - first.timestamp))
+    )
+    .unwrap();
+    for (i, window) in windows.iter().enumerate() {
+        write!(
+            res,
+            "  last {}: discharge {}, charge {}",
+            battery::format_duration(*window),
+            format_rate(history.average_discharge_rate(now, *window), last.unit),
+            format_rate(history.average_charge_rate(now, *window), last.unit),
+        )
+        .unwrap();
+        if i + 1 < windows.len() {
+            res.push('\n');
+        }
+    }
+    res
+}
+
+#[derive(Debug, Serialize)]
+struct Row<'s> {
+    battery: &'s str,
+    timestamp: u64,
+    remaining: u32,
+    actual_max: u32,
+    status: BatteryStatus,
+    unit: CapacityUnit,
+}
+
+/// Writes the samples of each battery as CSV or JSON.
+pub fn export(
+    mut out: impl io::Write,
+    histories: &[(String, History)],
+    format: ExportFormat,
+) -> io::Result<()> {
+    let rows = histories.iter().flat_map(|(name, history)| {
+        history.samples.iter().map(move |s| Row {
+            battery: name,
+            timestamp: s.timestamp,
+            remaining: s.remaining,
+            actual_max: s.actual_max,
+            status: s.status,
+            unit: s.unit,
+        })
+    });
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()
+        }
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &rows.collect::<Vec<_>>())?;
+            writeln!(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 30m "), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(
+            parse_duration("7d"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("99999999999999999d").is_err());
+    }
+}
@@ -1,56 +1,407 @@
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 
 use notify_rust::{Hint, Notification, Urgency};
 
-use bincode::Options;
+use actions::NotifAction;
+use config::{BatteryConfig, Config};
+use critical::{CriticalAction, Outcome, SystemRunner};
+use decision::Notif;
 use directories::{BaseDirs, ProjectDirs, UserDirs};
 use lazy_static::lazy_static;
+use output::OutputFormat;
 use script_lib::{
-    battery::{Battery, BatteryStatus},
+    battery::{
+        self, history::unix_now, BackendKind, Battery, BatteryBackend, BatteryStatus,
+        ChargeThresholds, CombinedBattery, PowerSupplySource,
+    },
     log::init_fern,
     notif::NOTIF_ICON,
 };
+use signal_hook::{consts::SIGUSR1, flag};
+use state::{Mem, StateFile};
 use std::collections::HashMap;
 use std::fs;
-use std::fs::{File, OpenOptions};
-use std::io::Seek;
-use std::process::Command;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, Sender},
+    Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use transition::TransitionSetting;
+use warning::WarnLevel;
+
+mod actions;
+mod config;
+mod critical;
+mod decision;
+mod health;
+mod history;
+mod metrics;
+mod output;
+mod state;
+#[cfg(test)]
+mod testing;
+mod transition;
+mod warning;
 
 #[derive(Debug, Parser)]
 #[clap(version, about = "Checks battery levels, outputs battery percentages")]
 pub struct Args {
     /// output logging level
-    #[clap(short, long, default_value = "Info", possible_values = ["Error", "Warn", "Info", "Debug", "Trace"])]
+    #[clap(short, long, env = "CHECK_BATTERY_LOG_LVL", default_value = "Info", possible_values = ["Error", "Warn", "Info", "Debug", "Trace"])]
     pub log_lvl: log::LevelFilter,
+    /// config file; settings given as flags or environment variables take precedence over it [default: $XDG_CONFIG_HOME/check-battery/config.toml]
+    #[clap(short, long, env = "CHECK_BATTERY_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// where to read batteries from (upower requires the `upower` feature)
+    #[clap(short, long, env = "CHECK_BATTERY_BACKEND", default_value = "sysfs", possible_values = ["sysfs", "upower"])]
+    pub backend: BackendKind,
     /// notification level; enables notifications if specified (Warn = critical level warnings, Info = +Max Level Notification, Trace = every run)
-    #[clap(short, long, possible_values = ["None", "Warn", "Info", "Trace"])]
+    #[clap(short, long, env = "CHECK_BATTERY_NOTIF_LVL", possible_values = ["None", "Warn", "Info", "Trace"])]
     pub notif_lvl: Option<log::LevelFilter>,
     /// battery level at which to begin sending warning notifications
-    #[clap(short, long, default_value = "20.0", requires = "notif-lvl")]
+    #[clap(short, long, env = "CHECK_BATTERY_WARN_MIN", default_value = "20.0")]
     pub warn_min: f32,
-    /// average battery level at which to hibernate the system
-    #[clap(short, long)]
+    /// warning level as PERCENT[:URGENCY[:TEXT]] (e.g. 10:critical:Battery critical), each sent once when crossed; may be given multiple times, replacing --warn-min
+    #[clap(long, multiple_occurrences = true)]
+    pub warn_level: Vec<WarnLevel>,
+    /// how many points a battery must climb back above a warning level before it can fire again
+    #[clap(long, env = "CHECK_BATTERY_WARN_HYSTERESIS", default_value = "2.0")]
+    pub warn_hysteresis: f32,
+    /// how to notify a status transition, as TRANSITION[:URGENCY|off] (transitions: charging, unplugged, limit, unknown), at --notif-lvl Info and above; may be given multiple times
+    #[clap(long, multiple_occurrences = true)]
+    pub transition: Vec<TransitionSetting>,
+    /// charge limit (%) to assume instead of the one reported by each battery
+    #[clap(long, env = "CHECK_BATTERY_CHARGE_LIMIT", validator = parse_percent)]
+    pub charge_limit: Option<u8>,
+    /// fraction of the charge limit (or of 100%) past which a charging battery counts as full
+    #[clap(long, env = "CHECK_BATTERY_FULL_THRESHOLD", default_value = "0.95")]
+    pub full_threshold: f32,
+    /// also warn when a peripheral (wireless mouse, keyboard, ...) crosses a warning level or reports a low level, once per level like batteries
+    #[clap(long)]
+    pub peripherals: bool,
+    /// average battery level at which to hibernate the system (or run --stop-action)
+    #[clap(short, long, env = "CHECK_BATTERY_STOP_MIN")]
     pub stop_min: Option<f32>,
-    /// batteries to check
+    /// what to do below --stop-min
+    #[clap(long, env = "CHECK_BATTERY_STOP_ACTION", default_value = "hibernate", possible_values = ["hibernate", "suspend", "hybrid-sleep", "poweroff"], conflicts_with = "stop-command")]
+    pub stop_action: CriticalAction,
+    /// shell command to run below --stop-min, instead of --stop-action
+    #[clap(long, env = "CHECK_BATTERY_STOP_COMMAND")]
+    pub stop_command: Option<String>,
+    /// how long to count down before running the stop action; plugging in during the countdown cancels it
+    #[clap(long, env = "CHECK_BATTERY_STOP_GRACE", default_value = "60s", parse(try_from_str = history::parse_duration))]
+    pub stop_grace: Duration,
+    /// how to print battery levels on stdout
+    #[clap(short, long, env = "CHECK_BATTERY_FORMAT", default_value = "plain", possible_values = ["plain", "json", "waybar", "i3blocks", "polybar"])]
+    pub format: OutputFormat,
+    /// also write battery level, health, power draw and status to this file as OpenMetrics gauges (e.g. for node_exporter's textfile collector)
+    #[clap(long, env = "CHECK_BATTERY_METRICS")]
+    pub metrics: Option<PathBuf>,
+    /// print notifications as JSON on stderr instead of sending them, leaving the state file, history and stop action alone
+    #[clap(long)]
+    pub dry_run: bool,
+    /// keep running, checking again whenever a power supply changes; needed (like --daemon) for the Hibernate/Suspend/Snooze buttons on critical notifications
+    #[clap(long)]
+    pub watch: bool,
+    /// with --watch, also check every X seconds even if no power supply event arrives
+    #[clap(long, default_value = "60", requires = "watch")]
+    pub watch_interval: u64,
+    /// keep running, keeping state in memory instead of $XDG_RUNTIME_DIR/check-battery/mem; SIGUSR1 forces a check. Critical notifications get Hibernate/Suspend/Snooze buttons only with this or --watch
+    #[clap(short, long, conflicts_with = "watch")]
+    pub daemon: bool,
+    /// with --daemon, time between checks (e.g. 30s, 2m)
+    #[clap(long, default_value = "60s", requires = "daemon", parse(try_from_str = history::parse_duration))]
+    pub poll_interval: Duration,
+    /// drop charge history samples older than this (e.g. 12h, 7d)
+    #[clap(long, default_value = "7d", parse(try_from_str = history::parse_duration))]
+    pub history_max_age: Duration,
+    /// batteries to check; defaults to every system battery
     #[clap()]
     pub batteries: Vec<String>,
+    /// batteries and peripherals to leave out when checking everything, from the config file
+    #[clap(skip)]
+    pub ignore: Vec<String>,
+    /// per-battery settings from the config file
+    #[clap(skip)]
+    pub overrides: HashMap<String, BatteryConfig>,
+    #[clap(subcommand)]
+    pub cmd: Option<Cmd>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Cmd {
+    /// print capacity, wear, cycle count and identity of each battery
+    Health {
+        /// batteries to report; defaults to every system battery
+        #[clap()]
+        batteries: Vec<String>,
+    },
+    /// print average charge and discharge rates from the recorded charge history
+    History {
+        /// window over which to average rates (e.g. 30m, 12h, 7d); may be given multiple times
+        #[clap(short, long, multiple_occurrences = true, default_values = &["1h", "24h"], parse(try_from_str = history::parse_duration))]
+        window: Vec<Duration>,
+        /// print every sample in this format instead
+        #[clap(short, long, possible_values = ["csv", "json"])]
+        export: Option<history::ExportFormat>,
+        /// batteries to report; defaults to every system battery
+        #[clap()]
+        batteries: Vec<String>,
+    },
+    /// print or set the charge thresholds of each battery (e.g. `limit --end 80`)
+    Limit {
+        /// battery level below which to start charging
+        #[clap(short, long, validator = parse_percent)]
+        start: Option<u8>,
+        /// battery level at which to stop charging
+        #[clap(short, long, validator = parse_percent)]
+        end: Option<u8>,
+        /// batteries to configure; defaults to every system battery
+        #[clap()]
+        batteries: Vec<String>,
+    },
+    /// inspect the config file
+    Config {
+        #[clap(subcommand)]
+        cmd: ConfigCmd,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCmd {
+    /// validate the config file, and warn about overrides for batteries that aren't present; fails if there is no config file
+    Check,
+}
+
+fn parse_percent(s: &str) -> Result<(), String> {
+    match s.parse::<u8>() {
+        Ok(p) if p <= 100 => Ok(()),
+        _ => Err(format!("not a percentage: {s}")),
+    }
+}
+
+fn format_thresholds(name: &str, thresholds: &ChargeThresholds) -> String {
+    let fmt = |t: Option<u8>| t.map_or_else(|| "-".to_owned(), |t| format!("{t}%"));
+    format!(
+        "{}: start {}, end {}",
+        name,
+        fmt(thresholds.start),
+        fmt(thresholds.end)
+    )
+}
+
+/// Ensures the existence of $XDG_STATE_HOME/check-battery/history.
+fn history_dir() -> Option<PathBuf> {
+    let dir = BaseDirs::new()?
+        .state_dir()?
+        .join("check-battery")
+        .join("history");
+    match fs::create_dir_all(&dir) {
+        Ok(()) => Some(dir),
+        Err(e) => {
+            log::error!("Failed to create {:?}: {:?}", dir, e);
+            None
+        }
+    }
+}
+
+/// Falls back to every system battery that isn't ignored if none are named.
+fn resolve_batteries(
+    backend: &dyn BatteryBackend,
+    batteries: Vec<String>,
+    ignore: &[String],
+) -> Vec<String> {
+    if !batteries.is_empty() {
+        return batteries;
+    }
+    let mut names = backend.battery_names().unwrap_or_else(|e| {
+        log::error!("Failed to list batteries: {:?}", e);
+        Vec::new()
+    });
+    names.retain(|name| !ignore.contains(name));
+    names
+}
+
+impl Args {
+    /// The --warn-level levels, or a single critical level at --warn-min.
+    fn warn_levels(&self) -> Vec<WarnLevel> {
+        match self.warn_level.is_empty() {
+            true => vec![WarnLevel::at(self.warn_min)],
+            false => self.warn_level.clone(),
+        }
+    }
+}
+
+/// Applies any snooze picked on a notification since the last call.
+fn take_snoozes(actions: &Receiver<Option<NotifAction>>, mem: &mut Mem) {
+    for action in actions.try_iter() {
+        if action == Some(NotifAction::Snooze) {
+            log::info!(
+                "Snoozing notifications for {}",
+                battery::format_duration(actions::SNOOZE)
+            );
+            mem.snoozed_until = Some(unix_now() + actions::SNOOZE.as_secs());
+        }
+    }
 }
 
 lazy_static! {
-    static ref BINCODE_OPTS: bincode::DefaultOptions = bincode::DefaultOptions::new();
+    pub static ref BINCODE_OPTS: bincode::DefaultOptions = bincode::DefaultOptions::new();
 }
 
 fn main() -> std::io::Result<()> {
-    let args @ Args {
-        log_lvl,
-        notif_lvl,
-        warn_min,
-        stop_min,
-        ..
-    } = Args::parse();
-    init_fern(std::io::stderr(), log_lvl);
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    init_fern(std::io::stderr(), args.log_lvl);
+    let config_path = match args.config.clone().or_else(config::default_path) {
+        Some(path) => path,
+        None => {
+            log::error!("Failed to get XDG base dirs");
+            std::process::exit(1);
+        }
+    };
+    let config = match Config::load(&config_path, args.config.is_some()) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Invalid config file {:?}: {}", config_path, e);
+            std::process::exit(1);
+        }
+    };
+    log::debug!("config: {:?}", &config);
+    config.apply(&mut args, &matches);
+    let backend = match args.backend.open() {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!("Failed to open {} backend: {}", args.backend, e);
+            std::process::exit(1);
+        }
+    };
+    match args.cmd.take() {
+        Some(Cmd::Health { batteries }) => {
+            for bat_name in resolve_batteries(&*backend, batteries, &args.ignore) {
+                match backend.battery(&bat_name) {
+                    Ok(battery) => println!("{}", health::report(&battery)),
+                    Err(e) => log::warn!("Skipping {}: {}", bat_name, e),
+                }
+            }
+            return Ok(());
+        }
+        Some(Cmd::History {
+            window,
+            export,
+            batteries,
+        }) => {
+            let dir = match history_dir() {
+                Some(dir) => dir,
+                None => return Ok(()),
+            };
+            let histories: Vec<_> = resolve_batteries(&*backend, batteries, &args.ignore)
+                .into_iter()
+                .map(|name| {
+                    let history = history::load(&dir, &name);
+                    (name, history)
+                })
+                .collect();
+            match export {
+                Some(format) => history::export(std::io::stdout(), &histories, format)?,
+                None => {
+                    let now = unix_now();
+                    for (name, history) in &histories {
+                        println!("{}", history::report(name, history, now, &window));
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Some(Cmd::Limit {
+            start,
+            end,
+            batteries,
+        }) => {
+            let mut failed = false;
+            // thresholds are only exposed through sysfs
+            for bat_name in
+                resolve_batteries(&PowerSupplySource::default(), batteries, &args.ignore)
+            {
+                let res = if start.is_none() && end.is_none() {
+                    battery::charge_thresholds(&bat_name)
+                } else {
+                    battery::set_charge_thresholds(&bat_name, start, end)
+                };
+                match res {
+                    Ok(thresholds) => println!("{}", format_thresholds(&bat_name, &thresholds)),
+                    Err(e) => {
+                        log::error!("{}: {}", bat_name, e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Cmd::Config {
+            cmd: ConfigCmd::Check,
+        }) => {
+            // a missing file loads as the defaults, which there's nothing to check about
+            if !config_path.is_file() {
+                log::error!("No config file at {:?}", config_path);
+                std::process::exit(1);
+            }
+            // reaching this far means the file parsed
+            println!("{}: ok", config_path.display());
+            let names = backend.battery_names().unwrap_or_else(|e| {
+                log::error!("Failed to list batteries: {:?}", e);
+                Vec::new()
+            });
+            for name in args.overrides.keys().filter(|name| !names.contains(name)) {
+                log::warn!("No battery named {} for [battery.{}]", name, name);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    let history_dir = history_dir();
+    args.batteries =
+        resolve_batteries(&*backend, std::mem::take(&mut args.batteries), &args.ignore);
     log::debug!("Checking levels of {:?}", args.batteries);
 
+    let record = |mem: &Mem| {
+        if args.dry_run {
+            return;
+        }
+        if let Some(dir) = &history_dir {
+            history::record(
+                dir,
+                mem.batteries.values(),
+                unix_now(),
+                args.history_max_age,
+            );
+        }
+    };
+
+    let (actions_tx, actions_rx) = mpsc::channel();
+    if args.daemon {
+        let force = Arc::new(AtomicBool::new(false));
+        flag::register(SIGUSR1, Arc::clone(&force))?;
+        let mut mem = Mem::default();
+        loop {
+            take_snoozes(&actions_rx, &mut mem);
+            let (next, stop_at) = check(&args, &*backend, &mem, &actions_tx);
+            mem = next;
+            record(&mem);
+            if let Some(percent) = stop_at {
+                stop(&args, &*backend, percent);
+            }
+            wait(args.poll_interval, &force);
+        }
+    }
+
     log::debug!("Ensuring existence of $XDG_RUNTIME_DIR/check-battery...");
     let base_dirs = BaseDirs::new().expect("failed to get XDG base dirs");
     let runtime_path = base_dirs
@@ -60,88 +411,332 @@ fn main() -> std::io::Result<()> {
     fs::create_dir_all(&runtime_path)?;
 
     let mem_path = runtime_path.join("mem");
-    log::debug!("Deserializing {:?}", &mem_path);
-    let mut mem_file: File = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&mem_path)
-        .unwrap_or_else(|_| panic!("Could not open {:?}", &mem_path));
-    let mem_batteries: HashMap<String, Battery> = match BINCODE_OPTS.deserialize_from(&mem_file) {
-        Ok(m) => m,
-        Err(e) => {
-            log::error!("Deserialization: {:?}", e);
-            HashMap::default()
+    // hold the lock only while checking, so a --watch process doesn't block oneshot runs
+    let check_locked = |actions: &Sender<Option<NotifAction>>| -> std::io::Result<()> {
+        let state = StateFile::lock(&mem_path)?;
+        log::debug!("Deserializing {:?}", state.path());
+        let mut mem = state.load();
+        log::debug!("mem: {:?}", &mem);
+        take_snoozes(&actions_rx, &mut mem);
+        let (mem, stop_at) = check(&args, &*backend, &mem, actions);
+        if !args.dry_run {
+            if let Err(e) = state.save(&mem) {
+                log::error!("Failed to write {:?}: {:?}", state.path(), e);
+            }
+            record(&mem);
         }
+        // the countdown, and the hibernation after it, mustn't keep other runs waiting
+        drop(state);
+        if let Some(percent) = stop_at {
+            stop(&args, &*backend, percent);
+        }
+        Ok(())
     };
-    log::debug!("mem_batteries: {:?}", &mem_batteries);
+
+    if !args.watch {
+        return check_locked(&actions_tx);
+    }
+
+    let mut watcher = battery::watch()?;
+    let interval = Duration::from_secs(args.watch_interval);
+    loop {
+        check_locked(&actions_tx)?;
+        match watcher.recv(Some(interval)) {
+            Ok(Some(event)) => log::debug!("Power supply event: {} {}", event.action, event.name),
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("Failed to receive power supply event: {}", e);
+                thread::sleep(interval);
+            }
+        }
+    }
+}
+
+/// Sleeps for `dur`, or until `force` is set (by SIGUSR1).
+fn wait(dur: Duration, force: &AtomicBool) {
+    let start = Instant::now();
+    let sleep_dur = Duration::from_millis(200);
+    while start.elapsed() < dur && !force.load(Ordering::Relaxed) {
+        thread::sleep(sleep_dur);
+    }
+    if force.swap(false, Ordering::Relaxed) {
+        log::debug!("Received SIGUSR1, checking now");
+    }
+}
+
+/// Reads each battery, sends notifications and prints levels; returns the state to carry over to the next check.
+///
+/// Also returns the total battery percent if it's below --stop-min, for the caller to [stop] once it's done with the state file.
+///
+/// Actions picked on critical notifications are sent on `actions`.
+fn check(
+    args: &Args,
+    backend: &dyn BatteryBackend,
+    mem: &Mem,
+    actions: &Sender<Option<NotifAction>>,
+) -> (Mem, Option<f32>) {
+    let Args {
+        notif_lvl,
+        warn_hysteresis,
+        charge_limit,
+        full_threshold,
+        stop_min,
+        ..
+    } = *args;
+    let warn_levels = args.warn_levels();
+    let plugged_in = backend.is_plugged_in().unwrap_or_else(|e| {
+        log::warn!("Failed to check external power supplies: {:?}", e);
+        false
+    });
+    log::debug!("plugged_in: {}", plugged_in);
+    let now = unix_now();
+    let snoozed_until = mem.snoozed_until.filter(|&until| until > now);
+    if let Some(until) = snoozed_until {
+        log::debug!("Notifications snoozed for {}s", until - now);
+    }
+    // snoozing keeps levels from firing, so they still fire once it expires
+    let notif_lvl = notif_lvl.filter(|_| snoozed_until.is_none());
 
     let mut batteries: HashMap<String, Battery> = HashMap::new();
+    let mut warnings: HashMap<String, Vec<f32>> = HashMap::new();
     for bat_name in &args.batteries {
-        let battery = match Battery::from_name(bat_name) {
+        let mut battery = match backend.battery(bat_name) {
             Ok(b) => b,
-            Err(_e) => continue,
+            Err(e) => {
+                log::warn!("Skipping {}: {}", bat_name, e);
+                continue;
+            }
         };
+        let overrides = args.overrides.get(bat_name);
+        if let Some(limit) = overrides.and_then(|o| o.charge_limit).or(charge_limit) {
+            battery.charge_limit = Some(limit);
+        }
+        let full_threshold = overrides
+            .and_then(|o| o.full_threshold)
+            .unwrap_or(full_threshold);
+        let bat_levels = overrides.and_then(BatteryConfig::warn_levels);
+        let warn_levels = bat_levels.as_deref().unwrap_or(&warn_levels);
         batteries.insert(battery.name.clone(), battery);
         let battery = &batteries[bat_name];
+        let display_name = overrides
+            .and_then(|o| o.name.as_deref())
+            .unwrap_or(&battery.name);
 
         let rem = battery.part_actual();
         let percent = rem * 100.0;
+        let fired = warnings
+            .entry(battery.name.clone())
+            .or_insert_with(|| mem.warnings.get(&battery.name).cloned().unwrap_or_default());
+        let warn_level = warning::update(
+            warn_levels,
+            fired,
+            percent,
+            warn_hysteresis,
+            matches!(
+                battery.status,
+                BatteryStatus::Unknown | BatteryStatus::Discharging
+            ) && !plugged_in
+                && snoozed_until.is_none(),
+        );
 
         if let Some(notif_lvl) = notif_lvl {
-            log::debug!("notification icon: {:?}", NOTIF_ICON.to_str());
-            let mut base_notif = Notification::new();
-            base_notif
-                .appname("check-battery")
-                .summary(&format!("Battery: {percent}%"))
-                .body(&format!("{} ({})", battery.name, battery.status))
-                .icon(NOTIF_ICON.to_str().unwrap())
-                .hint(Hint::Category("system".to_string()))
-                .hint(Hint::Custom(
-                    "x-dunst-stack-tag".to_owned(),
-                    battery.name.clone(),
-                ))
-                .hint(Hint::CustomInt("value".to_owned(), percent as i32));
-            match (battery.status, notif_lvl) {
-                (BatteryStatus::Unknown | BatteryStatus::Discharging, _) if percent <= warn_min => {
-                    Some(base_notif.urgency(Urgency::Critical).timeout(0))
-                }
-                (_, log::LevelFilter::Info)
-                    if battery.is_full(0.95)
-                        && !mem_batteries
-                            .get(&battery.name)
-                            .map_or(false, |bat| bat.is_full(0.95)) =>
-                {
-                    Some(base_notif.urgency(Urgency::Low))
-                }
-                (_, log::LevelFilter::Trace) => Some(base_notif.urgency(Urgency::Low)),
-                _ => None,
+            let notif = decision::battery_notif(
+                battery,
+                mem.batteries.get(&battery.name),
+                warn_level,
+                display_name,
+                notif_lvl,
+                full_threshold,
+                &args.transition,
+            );
+            if let Some(notif) = notif {
+                send(args, &notif, actions);
             }
-            .and_then(|not| not.show().ok());
         }
     }
-    for (name, bat) in batteries.iter() {
-        println!("{}={}", name, bat.part_actual());
+    if args.peripherals {
+        warnings.extend(check_peripherals(
+            args,
+            backend,
+            notif_lvl,
+            &warn_levels,
+            mem,
+            snoozed_until.is_none(),
+            actions,
+        ));
     }
-    if let Some(stop_min) = stop_min {
-        let percent: f32 = 100.0 * batteries.values().map(|bat| bat.part_actual()).sum::<f32>()
-            / batteries.len() as f32;
-        if percent <= stop_min {
-            log::warn!("Total battery percent ({percent}%) is below the hibernation threshold ({stop_min}%).");
-            // Command::new("systemctl").arg("hibernate").spawn().unwrap();
+    let mut sorted: Vec<&Battery> = batteries.values().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    print!("{}", output::render(args.format, &sorted, &warn_levels));
+    let _ = std::io::stdout().flush();
+    if let Some(path) = &args.metrics {
+        if let Err(e) = metrics::write(path, &sorted) {
+            log::error!("Failed to write metrics to {:?}: {:?}", path, e);
         }
     }
+    let stop_at = match (stop_min, CombinedBattery::new(batteries.values())) {
+        (Some(stop_min), Some(combined)) => {
+            let percent = 100.0 * combined.part_actual();
+            (percent <= stop_min && !plugged_in).then(|| {
+                log::warn!("Total battery percent ({percent}%) is below the hibernation threshold ({stop_min}%).");
+                percent
+            })
+        }
+        _ => None,
+    };
+
+    let mem = Mem {
+        batteries,
+        warnings,
+        snoozed_until,
+    };
+    (mem, stop_at)
+}
 
-    log::debug!("Truncating {:?}...", &mem_path);
-    mem_file
-        .set_len(0)
-        .unwrap_or_else(|_| panic!("Unable to truncate {:?}", &mem_path));
-    mem_file
-        .rewind()
-        .unwrap_or_else(|_| panic!("Unable to rewind {:?}", &mem_path));
-    log::debug!("Serializing {:?} into {:?}", &batteries, &mem_path);
-    if let Err(e) = BINCODE_OPTS.serialize_into(mem_file, &batteries) {
-        log::error!("Serialization: {:?}", e);
+/// Counts down --stop-grace with a critical notification, then runs the stop action unless external power returns.
+fn stop(args: &Args, backend: &dyn BatteryBackend, percent: f32) {
+    let action = match &args.stop_command {
+        Some(cmd) => CriticalAction::Custom(cmd.clone()),
+        None => args.stop_action.clone(),
+    };
+    if args.dry_run {
+        let notif = Notif {
+            tag: "critical-action".to_owned(),
+            category: "system",
+            summary: format!(
+                "Battery critical ({percent:.0}%): {} in {}s",
+                action.verb(),
+                args.stop_grace.as_secs()
+            ),
+            body: "Plug in to cancel".to_owned(),
+            urgency: Urgency::Critical,
+            persistent: true,
+            actions: false,
+            value: None,
+        };
+        eprintln!("{}", serde_json::to_string(&notif).unwrap());
+        return;
+    }
+    let mut notif = Notification::new();
+    notif
+        .appname("check-battery")
+        .icon(NOTIF_ICON.to_str().unwrap())
+        .hint(Hint::Category("system".to_string()))
+        .hint(Hint::Custom(
+            "x-dunst-stack-tag".to_owned(),
+            "critical-action".to_owned(),
+        ));
+    let outcome = critical::countdown(
+        &action,
+        args.stop_grace,
+        Duration::from_secs(5),
+        &mut SystemRunner,
+        || {
+            backend.is_plugged_in().unwrap_or_else(|e| {
+                log::warn!("Failed to check external power supplies: {:?}", e);
+                false
+            })
+        },
+        |remaining, step| {
+            let _ = notif
+                .summary(&format!(
+                    "Battery critical ({percent:.0}%): {} in {}s",
+                    action.verb(),
+                    remaining.as_secs()
+                ))
+                .body("Plug in to cancel")
+                .urgency(Urgency::Critical)
+                .timeout(0)
+                .show();
+            thread::sleep(step);
+        },
+    );
+    match outcome {
+        Outcome::Ran => log::info!("Ran {}", action),
+        Outcome::Cancelled => {
+            log::info!("External power connected; cancelled {}", action);
+            let _ = notif
+                .summary(&format!("Cancelled {action}"))
+                .body("External power connected")
+                .urgency(Urgency::Low)
+                .timeout(5000)
+                .show();
+        }
+        Outcome::Failed(e) => {
+            log::error!("Failed to {}: {}", action, e);
+            let _ = notif
+                .summary(&format!("Failed to {action}"))
+                .body(&e.to_string())
+                .urgency(Urgency::Critical)
+                .show();
+        }
+    }
+}
+
+/// Sends a notification for each peripheral that just crossed a warning level, or for every peripheral at Trace.
+///
+/// Levels only fire if `arm`; returns the fired levels to carry over, keyed like [Mem::warnings].
+fn check_peripherals(
+    args: &Args,
+    backend: &dyn BatteryBackend,
+    notif_lvl: Option<log::LevelFilter>,
+    warn_levels: &[WarnLevel],
+    mem: &Mem,
+    arm: bool,
+    actions: &Sender<Option<NotifAction>>,
+) -> HashMap<String, Vec<f32>> {
+    let mut warnings = HashMap::new();
+    let peripherals = match backend.peripherals() {
+        Ok(p) => p,
+        Err(e) => {
+            log::warn!("Failed to list peripherals: {}", e);
+            return warnings;
+        }
+    };
+    for peripheral in peripherals
+        .into_iter()
+        .filter(|p| !args.ignore.contains(&p.name))
+    {
+        log::debug!("{}: {:?}", peripheral.name, peripheral);
+        let levels = args
+            .overrides
+            .get(&peripheral.name)
+            .and_then(BatteryConfig::warn_levels);
+        let key = decision::peripheral_tag(&peripheral.name);
+        let fired = warnings
+            .entry(key.clone())
+            .or_insert_with(|| mem.warnings.get(&key).cloned().unwrap_or_default());
+        let warn_level = warning::update(
+            levels.as_deref().unwrap_or(warn_levels),
+            fired,
+            warning::peripheral_percent(&peripheral),
+            args.warn_hysteresis,
+            arm && peripheral.status != BatteryStatus::Charging,
+        );
+        if let Some(notif_lvl) = notif_lvl {
+            if let Some(notif) = decision::peripheral_notif(&peripheral, warn_level, notif_lvl) {
+                send(args, &notif, actions);
+            }
+        }
+    }
+    warnings
+}
+
+/// Shows `notif`, or prints it as JSON on stderr with --dry-run, keeping stdout to the --format output.
+fn send(args: &Args, notif: &Notif, actions: &Sender<Option<NotifAction>>) {
+    // a oneshot run exits right away, leaving nothing to act on the buttons, so they're only offered to --watch and --daemon
+    let notif = &Notif {
+        actions: notif.actions && (args.watch || args.daemon),
+        ..notif.clone()
+    };
+    if args.dry_run {
+        eprintln!("{}", serde_json::to_string(notif).unwrap());
+        return;
+    }
+    let notification = notif.to_notification();
+    if notif.actions {
+        actions::show(notification, SystemRunner, actions.clone());
+    } else if let Err(e) = notification.show() {
+        log::warn!("Failed to show notification: {:?}", e);
     }
-    Ok(())
 }
@@ -0,0 +1,145 @@
+use script_lib::battery::{Battery, BatteryStatus};
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+use crate::output::status_label;
+use crate::state;
+
+const STATUSES: [BatteryStatus; 5] = [
+    BatteryStatus::Unknown,
+    BatteryStatus::Discharging,
+    BatteryStatus::Charging,
+    BatteryStatus::NotCharging,
+    BatteryStatus::Full,
+];
+
+/// A gauge with one sample per battery.
+struct Gauge {
+    name: &'static str,
+    unit: &'static str,
+    help: &'static str,
+    value: fn(&Battery) -> Option<f32>,
+}
+
+const GAUGES: [Gauge; 3] = [
+    Gauge {
+        name: "check_battery_level_ratio",
+        unit: "ratio",
+        help: "Charge left, as a fraction of the current full capacity.",
+        value: |b| Some(b.part_actual()),
+    },
+    Gauge {
+        name: "check_battery_health_ratio",
+        unit: "ratio",
+        help: "Current full capacity, as a fraction of the design capacity.",
+        value: |b| Some(b.health()),
+    },
+    Gauge {
+        name: "check_battery_power_watts",
+        unit: "watts",
+        help: "Power drawn from the battery, or into it while charging.",
+        value: Battery::power_draw,
+    },
+];
+
+/// Escapes a label value: `\`, `"` and newlines.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Formats the given batteries as OpenMetrics gauges, labelled by battery name.
+///
+/// Samples that aren't available or finite (e.g. power draw on some batteries) are left out.
+pub fn render(batteries: &[&Battery]) -> String {
+    let mut res = String::new();
+    for gauge in &GAUGES {
+        writeln!(res, "# TYPE {} gauge", gauge.name).unwrap();
+        writeln!(res, "# UNIT {} {}", gauge.name, gauge.unit).unwrap();
+        writeln!(res, "# HELP {} {}", gauge.name, gauge.help).unwrap();
+        for battery in batteries {
+            if let Some(value) = (gauge.value)(battery).filter(|v| v.is_finite()) {
+                let name = escape(&battery.name);
+                writeln!(res, "{}{{battery=\"{name}\"}} {value}", gauge.name).unwrap();
+            }
+        }
+    }
+    // one sample per status, so a query doesn't need to know the status names
+    writeln!(res, "# TYPE check_battery_status gauge").unwrap();
+    writeln!(
+        res,
+        "# HELP check_battery_status Whether the battery is in the given status."
+    )
+    .unwrap();
+    for battery in batteries {
+        let name = escape(&battery.name);
+        for status in STATUSES {
+            writeln!(
+                res,
+                "check_battery_status{{battery=\"{name}\",status=\"{}\"}} {}",
+                status_label(status),
+                (battery.status == status) as u8
+            )
+            .unwrap();
+        }
+    }
+    res.push_str("# EOF\n");
+    res
+}
+
+/// Atomically replaces `path` with the metrics for the given batteries, for node_exporter's textfile collector.
+pub fn write(path: &Path, batteries: &[&Battery]) -> io::Result<()> {
+    state::write_atomic(path, render(batteries).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture;
+    use std::fs;
+
+    #[test]
+    fn renders_gauges() {
+        let bat0 = fixture("BAT0");
+        let out = render(&[&bat0]);
+        assert!(out.starts_with("# TYPE check_battery_level_ratio gauge\n"));
+        assert!(out.ends_with("# EOF\n"));
+        assert!(out.contains(&format!(
+            "check_battery_level_ratio{{battery=\"BAT0\"}} {}\n",
+            bat0.part_actual()
+        )));
+        assert!(out.contains(&format!(
+            "check_battery_health_ratio{{battery=\"BAT0\"}} {}\n",
+            bat0.health()
+        )));
+        assert!(out.contains("check_battery_power_watts{battery=\"BAT0\"} 7.832\n"));
+        assert!(out.contains("check_battery_status{battery=\"BAT0\",status=\"discharging\"} 1\n"));
+        assert!(out.contains("check_battery_status{battery=\"BAT0\",status=\"charging\"} 0\n"));
+    }
+
+    #[test]
+    fn leaves_out_missing_samples() {
+        let mut bat0 = fixture("BAT0");
+        bat0.name = "a \"quoted\" \\ name".to_owned();
+        bat0.power_now = None;
+        bat0.current_now = None;
+        bat0.factory_max = 0;
+        let out = render(&[&bat0]);
+        assert!(!out.contains("check_battery_power_watts{"));
+        assert!(!out.contains("check_battery_health_ratio{"));
+        assert!(out.contains(r#"check_battery_level_ratio{battery="a \"quoted\" \\ name"}"#));
+    }
+
+    #[test]
+    fn writes_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("battery.prom");
+        write(&path, &[&fixture("BAT0")]).unwrap();
+        write(&path, &[]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), render(&[]));
+        assert!(!dir.path().join("battery.tmp").exists());
+    }
+}
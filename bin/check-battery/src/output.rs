@@ -0,0 +1,286 @@
+use notify_rust::Urgency;
+use script_lib::battery::{self, Battery, BatteryStatus, CombinedBattery};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::warning::WarnLevel;
+
+/// How to print battery levels on stdout.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum OutputFormat {
+    /// `name=fraction` lines
+    Plain,
+    Json,
+    Waybar,
+    I3blocks,
+    Polybar,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "plain" => Ok(Self::Plain),
+            "json" => Ok(Self::Json),
+            "waybar" => Ok(Self::Waybar),
+            "i3blocks" => Ok(Self::I3blocks),
+            "polybar" => Ok(Self::Polybar),
+            _ => Err(format!("unrecognized output format: {s}")),
+        }
+    }
+}
+
+/// How urgent a battery level is, according to the deepest warning level it's below.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum Severity {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    fn new(percent: f32, status: BatteryStatus, levels: &[WarnLevel]) -> Self {
+        if status == BatteryStatus::Charging {
+            return Self::Normal;
+        }
+        match levels
+            .iter()
+            .filter(|l| percent <= l.percent)
+            .min_by(|a, b| a.percent.total_cmp(&b.percent))
+        {
+            None => Self::Normal,
+            Some(l) if l.urgency == Urgency::Critical => Self::Critical,
+            Some(_) => Self::Warning,
+        }
+    }
+}
+
+/// Kebab-case name of a status, as used in CSS classes and metric labels.
+pub fn status_label(status: BatteryStatus) -> &'static str {
+    match status {
+        BatteryStatus::Unknown => "unknown",
+        BatteryStatus::Discharging => "discharging",
+        BatteryStatus::Charging => "charging",
+        BatteryStatus::NotCharging => "not-charging",
+        BatteryStatus::Full => "full",
+    }
+}
+
+/// CSS classes for a battery: its status, plus `warning` or `critical` when low.
+fn classes(status: BatteryStatus, severity: Severity) -> Vec<&'static str> {
+    let mut res = vec![status_label(status)];
+    match severity {
+        Severity::Normal => {}
+        Severity::Warning => res.push("warning"),
+        Severity::Critical => res.push("critical"),
+    }
+    res
+}
+
+fn icon(status: BatteryStatus, severity: Severity) -> &'static str {
+    match (status, severity) {
+        (BatteryStatus::Charging, _) => "⚡",
+        (BatteryStatus::Full | BatteryStatus::NotCharging, _) => "🔌",
+        (_, Severity::Warning | Severity::Critical) => "🪫",
+        _ => "🔋",
+    }
+}
+
+fn color(severity: Severity) -> Option<&'static str> {
+    match severity {
+        Severity::Normal => None,
+        Severity::Warning => Some("#FFAE00"),
+        Severity::Critical => Some("#FF0000"),
+    }
+}
+
+/// e.g. `BAT0: 64% (Discharging, 4h 14m left)`
+fn describe(battery: &Battery) -> String {
+    let percent = battery.part_actual() * 100.0;
+    match (battery.time_to_empty(), battery.time_to_full()) {
+        (Some(dur), _) => format!(
+            "{}: {percent:.0}% ({}, {} left)",
+            battery.name,
+            battery.status,
+            battery::format_duration(dur)
+        ),
+        (_, Some(dur)) => format!(
+            "{}: {percent:.0}% ({}, {} until full)",
+            battery.name,
+            battery.status,
+            battery::format_duration(dur)
+        ),
+        _ => format!("{}: {percent:.0}% ({})", battery.name, battery.status),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BatteryJson<'b> {
+    name: &'b str,
+    percent: f32,
+    status: BatteryStatus,
+    class: Vec<&'static str>,
+    icon: &'static str,
+    /// Seconds
+    time_to_empty: Option<u64>,
+    /// Seconds
+    time_to_full: Option<u64>,
+    /// Watts
+    power_draw: Option<f32>,
+}
+
+#[derive(Debug, Serialize)]
+struct Waybar {
+    text: String,
+    tooltip: String,
+    class: Vec<&'static str>,
+    percentage: u8,
+}
+
+/// Formats the given batteries for a status bar; bar formats show them combined into one.
+///
+/// The result ends with a newline, unless there's nothing to show.
+pub fn render(format: OutputFormat, batteries: &[&Battery], levels: &[WarnLevel]) -> String {
+    let mut res = String::new();
+    if format == OutputFormat::Plain {
+        for battery in batteries {
+            writeln!(res, "{}={}", battery.name, battery.part_actual()).unwrap();
+        }
+        return res;
+    }
+    if format == OutputFormat::Json {
+        let json: Vec<BatteryJson> = batteries
+            .iter()
+            .map(|b| {
+                let percent = b.part_actual() * 100.0;
+                let severity = Severity::new(percent, b.status, levels);
+                BatteryJson {
+                    name: &b.name,
+                    percent,
+                    status: b.status,
+                    class: classes(b.status, severity),
+                    icon: icon(b.status, severity),
+                    time_to_empty: b.time_to_empty().as_ref().map(Duration::as_secs),
+                    time_to_full: b.time_to_full().as_ref().map(Duration::as_secs),
+                    power_draw: b.power_draw(),
+                }
+            })
+            .collect();
+        writeln!(res, "{}", serde_json::to_string(&json).unwrap()).unwrap();
+        return res;
+    }
+
+    let combined = match CombinedBattery::new(batteries.iter().copied()) {
+        Some(c) => c,
+        None => return res,
+    };
+    let percent = combined.part_actual() * 100.0;
+    let severity = Severity::new(percent, combined.status, levels);
+    let icon = icon(combined.status, severity);
+    let text = format!("{icon} {percent:.0}%");
+    let time = combined
+        .time_to_empty()
+        .or_else(|| combined.time_to_full())
+        .map(battery::format_duration);
+    match format {
+        OutputFormat::Waybar => {
+            let waybar = Waybar {
+                text,
+                tooltip: batteries
+                    .iter()
+                    .map(|b| describe(b))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                class: classes(combined.status, severity),
+                percentage: percent.round().clamp(0.0, 100.0) as u8,
+            };
+            writeln!(res, "{}", serde_json::to_string(&waybar).unwrap()).unwrap();
+        }
+        // full text, short text, then color
+        OutputFormat::I3blocks => {
+            match &time {
+                Some(time) => writeln!(res, "{text} ({time})").unwrap(),
+                None => writeln!(res, "{text}").unwrap(),
+            }
+            writeln!(res, "{text}").unwrap();
+            if let Some(color) = color(severity) {
+                writeln!(res, "{color}").unwrap();
+            }
+        }
+        OutputFormat::Polybar => {
+            let text = match &time {
+                Some(time) => format!("{text} ({time})"),
+                None => text,
+            };
+            match color(severity) {
+                Some(color) => writeln!(res, "%{{F{color}}}{text}%{{F-}}").unwrap(),
+                None => writeln!(res, "{text}").unwrap(),
+            }
+        }
+        OutputFormat::Plain | OutputFormat::Json => unreachable!(),
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture;
+
+    fn levels() -> Vec<WarnLevel> {
+        vec!["20:normal".parse().unwrap(), "10".parse().unwrap()]
+    }
+
+    #[test]
+    fn plain() {
+        let bat0 = fixture("BAT0");
+        assert_eq!(
+            render(OutputFormat::Plain, &[&bat0], &levels()),
+            format!("BAT0={}\n", bat0.part_actual())
+        );
+    }
+
+    #[test]
+    fn waybar() {
+        let bat0 = fixture("BAT0");
+        let out = render(OutputFormat::Waybar, &[&bat0], &levels());
+        assert_eq!(
+            out,
+            "{\"text\":\"🔋 65%\",\"tooltip\":\"BAT0: 65% (Discharging, 4h 14m left)\",\"class\":[\"discharging\"],\"percentage\":65}\n"
+        );
+    }
+
+    #[test]
+    fn severity_classes() {
+        let mut bat0 = fixture("BAT0");
+        bat0.remaining = bat0.actual_max / 10;
+        let json: serde_json::Value =
+            serde_json::from_str(&render(OutputFormat::Json, &[&bat0], &levels())).unwrap();
+        assert_eq!(
+            json[0]["class"],
+            serde_json::json!(["discharging", "critical"])
+        );
+        assert_eq!(json[0]["icon"], "🪫");
+        assert_eq!(json[0]["time_to_empty"], 2356);
+
+        bat0.remaining = bat0.actual_max * 15 / 100;
+        assert_eq!(
+            render(OutputFormat::Polybar, &[&bat0], &levels()),
+            "%{F#FFAE00}🪫 15% (59m)%{F-}\n"
+        );
+        bat0.status = BatteryStatus::Charging;
+        assert_eq!(
+            render(OutputFormat::I3blocks, &[&bat0], &levels()),
+            "⚡ 15% (5h 34m)\n⚡ 15%\n"
+        );
+    }
+
+    #[test]
+    fn nothing_to_show() {
+        assert_eq!(render(OutputFormat::Waybar, &[], &levels()), "");
+        assert_eq!(render(OutputFormat::Json, &[], &levels()), "[]\n");
+    }
+}
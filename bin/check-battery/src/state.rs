@@ -0,0 +1,531 @@
+use bincode::Options;
+use script_lib::battery::{Battery, BatteryStatus, CapacityUnit};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::BINCODE_OPTS;
+
+/// Marks a versioned state file; files without it are from before versioning.
+const MAGIC: &[u8; 4] = b"CBST";
+/// Bump whenever [Mem] or [BatterySnapshot] changes, and teach [decode] to migrate the previous version.
+pub const VERSION: u32 = 3;
+
+/// State carried from one check to the next.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Mem {
+    pub batteries: HashMap<String, Battery>,
+    /// [WarnLevel::percent](crate::warning::WarnLevel::percent) of each warning level that has fired, per battery, and per peripheral under its [notification tag](crate::decision::peripheral_tag)
+    pub warnings: HashMap<String, Vec<f32>>,
+    /// Unix time until which battery notifications are snoozed
+    pub snoozed_until: Option<u64>,
+}
+
+/// The part of a [Battery] the next check compares against.
+///
+/// The state file stores this rather than [Battery] itself, so its layout only changes along with [VERSION].
+#[derive(Serialize, Deserialize)]
+struct BatterySnapshot {
+    name: String,
+    remaining: u32,
+    actual_max: u32,
+    factory_max: u32,
+    status: BatteryStatus,
+    unit: CapacityUnit,
+    charge_limit: Option<u8>,
+}
+
+impl From<&Battery> for BatterySnapshot {
+    fn from(b: &Battery) -> Self {
+        Self {
+            name: b.name.clone(),
+            remaining: b.remaining,
+            actual_max: b.actual_max,
+            factory_max: b.factory_max,
+            status: b.status,
+            unit: b.unit,
+            charge_limit: b.charge_limit,
+        }
+    }
+}
+
+impl From<BatterySnapshot> for Battery {
+    fn from(b: BatterySnapshot) -> Self {
+        Self {
+            name: b.name,
+            remaining: b.remaining,
+            actual_max: b.actual_max,
+            factory_max: b.factory_max,
+            status: b.status,
+            unit: b.unit,
+            power_now: None,
+            current_now: None,
+            voltage_now: None,
+            voltage_min_design: None,
+            cycle_count: None,
+            technology: None,
+            manufacturer: None,
+            model_name: None,
+            serial_number: None,
+            charge_limit: b.charge_limit,
+        }
+    }
+}
+
+/// [Mem] as laid out in the state file.
+#[derive(Serialize, Deserialize)]
+struct MemV3 {
+    batteries: HashMap<String, BatterySnapshot>,
+    warnings: HashMap<String, Vec<f32>>,
+    snoozed_until: Option<u64>,
+}
+
+impl From<&Mem> for MemV3 {
+    fn from(mem: &Mem) -> Self {
+        Self {
+            batteries: mem
+                .batteries
+                .iter()
+                .map(|(k, v)| (k.clone(), v.into()))
+                .collect(),
+            warnings: mem.warnings.clone(),
+            snoozed_until: mem.snoozed_until,
+        }
+    }
+}
+
+impl From<MemV3> for Mem {
+    fn from(mem: MemV3) -> Self {
+        Self {
+            batteries: snapshots(mem.batteries),
+            warnings: mem.warnings,
+            snoozed_until: mem.snoozed_until,
+        }
+    }
+}
+
+/// Rebuilds batteries from what the state file kept of them.
+fn snapshots(batteries: HashMap<String, impl Into<BatterySnapshot>>) -> HashMap<String, Battery> {
+    batteries
+        .into_iter()
+        .map(|(k, v)| (k, v.into().into()))
+        .collect()
+}
+
+/// [Battery] as of versions 1 and 2, which stored it whole.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct BatteryV2 {
+    name: String,
+    remaining: u32,
+    actual_max: u32,
+    factory_max: u32,
+    status: BatteryStatus,
+    unit: CapacityUnit,
+    power_now: Option<u32>,
+    current_now: Option<u32>,
+    voltage_now: Option<u32>,
+    voltage_min_design: Option<u32>,
+    cycle_count: Option<u32>,
+    technology: Option<String>,
+    manufacturer: Option<String>,
+    model_name: Option<String>,
+    serial_number: Option<String>,
+    charge_limit: Option<u8>,
+}
+
+impl From<BatteryV2> for Battery {
+    fn from(b: BatteryV2) -> Self {
+        Self {
+            name: b.name,
+            remaining: b.remaining,
+            actual_max: b.actual_max,
+            factory_max: b.factory_max,
+            status: b.status,
+            unit: b.unit,
+            power_now: b.power_now,
+            current_now: b.current_now,
+            voltage_now: b.voltage_now,
+            voltage_min_design: b.voltage_min_design,
+            cycle_count: b.cycle_count,
+            technology: b.technology,
+            manufacturer: b.manufacturer,
+            model_name: b.model_name,
+            serial_number: b.serial_number,
+            charge_limit: b.charge_limit,
+        }
+    }
+}
+
+/// Rebuilds batteries stored whole by versions 1 and 2.
+fn whole(batteries: HashMap<String, BatteryV2>) -> HashMap<String, Battery> {
+    batteries.into_iter().map(|(k, v)| (k, v.into())).collect()
+}
+
+/// [Mem] as of version 2.
+#[derive(Deserialize)]
+struct MemV2 {
+    batteries: HashMap<String, BatteryV2>,
+    warnings: HashMap<String, Vec<f32>>,
+    snoozed_until: Option<u64>,
+}
+
+impl From<MemV2> for Mem {
+    fn from(mem: MemV2) -> Self {
+        Self {
+            batteries: whole(mem.batteries),
+            warnings: mem.warnings,
+            snoozed_until: mem.snoozed_until,
+        }
+    }
+}
+
+/// [Mem] as of version 1, before snoozing.
+#[derive(Deserialize)]
+struct MemV1 {
+    batteries: HashMap<String, BatteryV2>,
+    warnings: HashMap<String, Vec<f32>>,
+}
+
+impl From<MemV1> for Mem {
+    fn from(mem: MemV1) -> Self {
+        Self {
+            batteries: whole(mem.batteries),
+            warnings: mem.warnings,
+            snoozed_until: None,
+        }
+    }
+}
+
+/// [Battery] as it was when the state file was a bare map of batteries.
+#[derive(Deserialize)]
+struct LegacyBattery {
+    name: String,
+    remaining: u32,
+    actual_max: u32,
+    factory_max: u32,
+    status: BatteryStatus,
+}
+
+impl From<LegacyBattery> for BatterySnapshot {
+    fn from(b: LegacyBattery) -> Self {
+        Self {
+            name: b.name,
+            remaining: b.remaining,
+            actual_max: b.actual_max,
+            factory_max: b.factory_max,
+            status: b.status,
+            unit: CapacityUnit::Energy,
+            charge_limit: None,
+        }
+    }
+}
+
+pub fn encode(mem: &Mem) -> bincode::Result<Vec<u8>> {
+    let mut bytes = MAGIC.to_vec();
+    BINCODE_OPTS.serialize_into(&mut bytes, &VERSION)?;
+    BINCODE_OPTS.serialize_into(&mut bytes, &MemV3::from(mem))?;
+    Ok(bytes)
+}
+
+/// Decodes a state file of any known version; anything unreadable is logged and replaced with empty state.
+pub fn decode(bytes: &[u8]) -> Mem {
+    if bytes.is_empty() {
+        return Mem::default();
+    }
+    let res = match bytes.strip_prefix(MAGIC) {
+        Some(mut rest) => match BINCODE_OPTS.deserialize_from::<_, u32>(&mut rest) {
+            Ok(VERSION) => BINCODE_OPTS.deserialize::<MemV3>(rest).map(Mem::from),
+            Ok(2) => BINCODE_OPTS.deserialize::<MemV2>(rest).map(Mem::from),
+            Ok(1) => BINCODE_OPTS.deserialize::<MemV1>(rest).map(Mem::from),
+            Ok(version) => {
+                log::warn!(
+                    "Ignoring state file of unknown version {} (expected {})",
+                    version,
+                    VERSION
+                );
+                return Mem::default();
+            }
+            Err(e) => Err(e),
+        },
+        None => BINCODE_OPTS
+            .deserialize::<HashMap<String, LegacyBattery>>(bytes)
+            .map(|batteries| {
+                log::info!("Migrating unversioned state file");
+                Mem {
+                    batteries: snapshots(batteries),
+                    ..Default::default()
+                }
+            }),
+    };
+    res.unwrap_or_else(|e| {
+        log::warn!("Ignoring unreadable state file: {}", e);
+        Mem::default()
+    })
+}
+
+/// The state file at `path`, held under an exclusive advisory lock on `path.lock` until dropped.
+///
+/// Writes go to a temporary file that then replaces the state file, so readers never see a partial write.
+pub struct StateFile {
+    path: PathBuf,
+    _lock: File,
+}
+
+impl StateFile {
+    /// Locks the state file, waiting for any other holder to release it.
+    pub fn lock(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let lock = lock(&path)?;
+        Ok(Self { path, _lock: lock })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self) -> Mem {
+        match fs::read(&self.path) {
+            Ok(bytes) => decode(&bytes),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Mem::default(),
+            Err(e) => {
+                log::error!("Failed to read {:?}: {:?}", self.path, e);
+                Mem::default()
+            }
+        }
+    }
+
+    pub fn save(&self, mem: &Mem) -> io::Result<()> {
+        let bytes = encode(mem).map_err(io::Error::other)?;
+        write_atomic(&self.path, &bytes)
+    }
+}
+
+/// Takes an exclusive lock on `path` with the extension `lock`, waiting for any other holder to release it.
+///
+/// The lock is released when the returned file is dropped.
+pub fn lock(path: &Path) -> io::Result<File> {
+    let lock = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path.with_extension("lock"))?;
+    flock(&lock, libc::LOCK_EX)?;
+    Ok(lock)
+}
+
+/// `File::lock` and friends need a newer rustc than we support.
+fn flock(file: &File, op: libc::c_int) -> io::Result<()> {
+    loop {
+        match unsafe { libc::flock(file.as_raw_fd(), op) } {
+            0 => return Ok(()),
+            _ => match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => continue,
+                e => return Err(e),
+            },
+        }
+    }
+}
+
+/// Writes `bytes` next to `path`, then renames it over `path`, so readers never see a partial file.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture;
+
+    fn mem() -> Mem {
+        let mut mem = Mem::default();
+        mem.batteries.insert(
+            "BAT0".to_owned(),
+            BatterySnapshot {
+                name: "BAT0".to_owned(),
+                remaining: 30,
+                actual_max: 50,
+                factory_max: 60,
+                status: BatteryStatus::Discharging,
+                unit: CapacityUnit::Energy,
+                charge_limit: None,
+            }
+            .into(),
+        );
+        mem.warnings.insert("BAT0".to_owned(), vec![20.0]);
+        mem.snoozed_until = Some(1_700_000_000);
+        mem
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = StateFile::lock(dir.path().join("mem")).unwrap();
+        assert_eq!(state.load(), Mem::default());
+        state.save(&mem()).unwrap();
+        assert_eq!(state.load(), mem());
+        assert!(!dir.path().join("mem.tmp").exists());
+    }
+
+    #[test]
+    fn keeps_a_snapshot() {
+        let mut bat0 = fixture("BAT0");
+        bat0.charge_limit = Some(80);
+        let mut mem = Mem::default();
+        mem.batteries.insert("BAT0".to_owned(), bat0.clone());
+        let loaded = decode(&encode(&mem).unwrap())
+            .batteries
+            .remove("BAT0")
+            .unwrap();
+        assert_eq!(
+            loaded,
+            Battery {
+                power_now: None,
+                voltage_now: None,
+                voltage_min_design: None,
+                cycle_count: None,
+                technology: None,
+                manufacturer: None,
+                model_name: None,
+                serial_number: None,
+                ..bat0
+            }
+        );
+    }
+
+    /// `mem`'s batteries as versions 1 and 2 stored them.
+    fn v2_batteries(mem: &Mem) -> HashMap<String, BatteryV2> {
+        mem.batteries
+            .iter()
+            .map(|(k, b)| {
+                let battery = BatteryV2 {
+                    name: b.name.clone(),
+                    remaining: b.remaining,
+                    actual_max: b.actual_max,
+                    factory_max: b.factory_max,
+                    status: b.status,
+                    unit: b.unit,
+                    power_now: None,
+                    current_now: None,
+                    voltage_now: None,
+                    voltage_min_design: None,
+                    cycle_count: None,
+                    technology: None,
+                    manufacturer: None,
+                    model_name: None,
+                    serial_number: None,
+                    charge_limit: b.charge_limit,
+                };
+                (k.clone(), battery)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn migrates_bare_map() {
+        #[derive(Serialize)]
+        struct Legacy<'s> {
+            name: &'s str,
+            remaining: u32,
+            actual_max: u32,
+            factory_max: u32,
+            status: BatteryStatus,
+        }
+        let legacy: HashMap<&str, Legacy> = [(
+            "BAT0",
+            Legacy {
+                name: "BAT0",
+                remaining: 30,
+                actual_max: 50,
+                factory_max: 60,
+                status: BatteryStatus::Discharging,
+            },
+        )]
+        .into();
+        let migrated = decode(&BINCODE_OPTS.serialize(&legacy).unwrap());
+        assert_eq!(migrated.batteries, mem().batteries);
+        assert!(migrated.warnings.is_empty());
+    }
+
+    #[test]
+    fn migrates_v1() {
+        #[derive(Serialize)]
+        struct V1<'m> {
+            batteries: HashMap<String, BatteryV2>,
+            warnings: &'m HashMap<String, Vec<f32>>,
+        }
+        let mem = mem();
+        let mut bytes = MAGIC.to_vec();
+        BINCODE_OPTS.serialize_into(&mut bytes, &1u32).unwrap();
+        BINCODE_OPTS
+            .serialize_into(
+                &mut bytes,
+                &V1 {
+                    batteries: v2_batteries(&mem),
+                    warnings: &mem.warnings,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            decode(&bytes),
+            Mem {
+                snoozed_until: None,
+                ..mem
+            }
+        );
+    }
+
+    #[test]
+    fn migrates_v2() {
+        #[derive(Serialize)]
+        struct V2<'m> {
+            batteries: HashMap<String, BatteryV2>,
+            warnings: &'m HashMap<String, Vec<f32>>,
+            snoozed_until: Option<u64>,
+        }
+        let mem = mem();
+        let mut bytes = MAGIC.to_vec();
+        BINCODE_OPTS.serialize_into(&mut bytes, &2u32).unwrap();
+        BINCODE_OPTS
+            .serialize_into(
+                &mut bytes,
+                &V2 {
+                    batteries: v2_batteries(&mem),
+                    warnings: &mem.warnings,
+                    snoozed_until: mem.snoozed_until,
+                },
+            )
+            .unwrap();
+        assert_eq!(decode(&bytes), mem);
+    }
+
+    #[test]
+    fn ignores_unknown_versions_and_garbage() {
+        let mut bytes = MAGIC.to_vec();
+        BINCODE_OPTS
+            .serialize_into(&mut bytes, &(VERSION + 1))
+            .unwrap();
+        bytes.extend(b"from the future");
+        assert_eq!(decode(&bytes), Mem::default());
+        assert_eq!(decode(b"\xff\xff\xff"), Mem::default());
+        assert_eq!(decode(&encode(&mem()).unwrap()), mem());
+    }
+
+    #[test]
+    fn lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mem");
+        let state = StateFile::lock(&path).unwrap();
+        let other = File::open(path.with_extension("lock")).unwrap();
+        assert!(flock(&other, libc::LOCK_EX | libc::LOCK_NB).is_err());
+        drop(state);
+        assert!(flock(&other, libc::LOCK_EX | libc::LOCK_NB).is_ok());
+    }
+}
@@ -0,0 +1,39 @@
+//! Helpers shared by the unit tests.
+
+use script_lib::battery::{Battery, BatteryStatus, CapacityUnit, PowerSupplySource};
+use std::sync::Mutex;
+
+/// Held while the environment is set or read, since tests run in parallel.
+pub static ENV: Mutex<()> = Mutex::new(());
+
+/// A battery at `percent` of both its current and design capacity, with nothing else known.
+pub fn battery(status: BatteryStatus, percent: u32) -> Battery {
+    Battery {
+        name: "BAT0".to_owned(),
+        remaining: percent,
+        actual_max: 100,
+        factory_max: 100,
+        status,
+        unit: CapacityUnit::Energy,
+        power_now: None,
+        current_now: None,
+        voltage_now: None,
+        voltage_min_design: None,
+        cycle_count: None,
+        technology: None,
+        manufacturer: None,
+        model_name: None,
+        serial_number: None,
+        charge_limit: None,
+    }
+}
+
+/// Reads battery `name` from the repo's power supply fixtures.
+pub fn fixture(name: &str) -> Battery {
+    PowerSupplySource::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../tests/fixtures/power_supply"
+    ))
+    .battery(name)
+    .unwrap()
+}
@@ -0,0 +1,206 @@
+use notify_rust::Urgency;
+use script_lib::battery::{Battery, BatteryStatus, FullState};
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::warning::parse_urgency;
+
+/// A change in a battery's status between two checks that can be notified.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Transition {
+    /// Started charging
+    Charging,
+    /// Went from charging or full to discharging
+    Unplugged,
+    /// Stopped charging at its charge limit
+    Limit,
+    /// Status became Unknown
+    Unknown,
+}
+
+impl Display for Transition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Transition::Charging => "charging",
+            Transition::Unplugged => "unplugged",
+            Transition::Limit => "limit",
+            Transition::Unknown => "unknown",
+        })
+    }
+}
+
+impl FromStr for Transition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "charging" => Ok(Self::Charging),
+            "unplugged" => Ok(Self::Unplugged),
+            "limit" => Ok(Self::Limit),
+            "unknown" => Ok(Self::Unknown),
+            _ => Err(format!("unrecognized transition: {s}")),
+        }
+    }
+}
+
+impl Transition {
+    /// Detects what changed from `prev` to `cur`, if anything worth notifying did.
+    pub fn between(prev: &Battery, cur: &Battery) -> Option<Self> {
+        match (prev.status, cur.status) {
+            (prev, cur) if prev == cur => None,
+            // the threshold only matters while charging
+            (_, BatteryStatus::NotCharging) if cur.full_state(1.0) == FullState::HeldAtLimit => {
+                Some(Self::Limit)
+            }
+            (_, BatteryStatus::Charging) => Some(Self::Charging),
+            (
+                BatteryStatus::Charging | BatteryStatus::Full | BatteryStatus::NotCharging,
+                BatteryStatus::Discharging,
+            ) => Some(Self::Unplugged),
+            (_, BatteryStatus::Unknown) => Some(Self::Unknown),
+            _ => None,
+        }
+    }
+
+    pub fn default_urgency(&self) -> Urgency {
+        match self {
+            Transition::Charging | Transition::Limit => Urgency::Low,
+            Transition::Unplugged | Transition::Unknown => Urgency::Normal,
+        }
+    }
+
+    /// Notification summary, e.g. `Unplugged: 80%`.
+    pub fn summary(&self, battery: &Battery) -> String {
+        let percent = battery.part_actual() * 100.0;
+        match self {
+            Transition::Charging => format!("Charging: {percent:.0}%"),
+            Transition::Unplugged => format!("Unplugged: {percent:.0}%"),
+            Transition::Limit => format!(
+                "Stopped charging at {}% limit",
+                battery.charge_limit.unwrap_or(100)
+            ),
+            Transition::Unknown => format!("Battery status unknown: {percent:.0}%"),
+        }
+    }
+}
+
+/// How to notify a [Transition], e.g. `unplugged:critical` or `charging:off`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransitionSetting {
+    pub transition: Transition,
+    /// `None` if disabled
+    pub urgency: Option<Urgency>,
+}
+
+impl FromStr for TransitionSetting {
+    type Err = String;
+
+    /// Parses `TRANSITION[:URGENCY|off]`; urgency defaults to [Transition::default_urgency].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (transition, urgency) = match s.split_once(':') {
+            Some((transition, urgency)) => (transition, Some(urgency.trim())),
+            None => (s, None),
+        };
+        let transition: Transition = transition.trim().parse()?;
+        let urgency = match urgency {
+            Some(u) if u.eq_ignore_ascii_case("off") => None,
+            Some(u) => Some(parse_urgency(u)?),
+            None => Some(transition.default_urgency()),
+        };
+        Ok(Self {
+            transition,
+            urgency,
+        })
+    }
+}
+
+/// The urgency to notify `transition` with, or `None` if it's disabled; later settings win.
+pub fn urgency(settings: &[TransitionSetting], transition: Transition) -> Option<Urgency> {
+    settings
+        .iter()
+        .rev()
+        .find(|s| s.transition == transition)
+        .map_or(Some(transition.default_urgency()), |s| s.urgency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn battery(status: BatteryStatus, percent: u32, charge_limit: Option<u8>) -> Battery {
+        Battery {
+            charge_limit,
+            ..testing::battery(status, percent)
+        }
+    }
+
+    #[test]
+    fn detects_transitions() {
+        use BatteryStatus::*;
+        let between = |prev, cur, limit| {
+            Transition::between(&battery(prev, 79, limit), &battery(cur, 79, limit))
+        };
+        assert_eq!(
+            between(Discharging, Charging, None),
+            Some(Transition::Charging)
+        );
+        assert_eq!(between(Unknown, Charging, None), Some(Transition::Charging));
+        assert_eq!(
+            between(Charging, Discharging, None),
+            Some(Transition::Unplugged)
+        );
+        assert_eq!(
+            between(Full, Discharging, None),
+            Some(Transition::Unplugged)
+        );
+        assert_eq!(between(Unknown, Discharging, None), None);
+        assert_eq!(
+            between(Charging, NotCharging, Some(80)),
+            Some(Transition::Limit)
+        );
+        assert_eq!(between(NotCharging, NotCharging, Some(80)), None);
+        // without a limit, not charging just means full
+        assert_eq!(between(Charging, NotCharging, None), None);
+        assert_eq!(
+            between(Discharging, Unknown, None),
+            Some(Transition::Unknown)
+        );
+        assert_eq!(between(Charging, Charging, None), None);
+    }
+
+    #[test]
+    fn settings() {
+        let settings: Vec<TransitionSetting> = ["unplugged:critical", "charging:off", "limit"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        assert_eq!(
+            urgency(&settings, Transition::Unplugged),
+            Some(Urgency::Critical)
+        );
+        assert_eq!(urgency(&settings, Transition::Charging), None);
+        assert_eq!(urgency(&settings, Transition::Limit), Some(Urgency::Low));
+        assert_eq!(
+            urgency(&settings, Transition::Unknown),
+            Some(Urgency::Normal)
+        );
+        let settings = [settings, vec!["charging:normal".parse().unwrap()]].concat();
+        assert_eq!(
+            urgency(&settings, Transition::Charging),
+            Some(Urgency::Normal)
+        );
+        assert!(TransitionSetting::from_str("charging:loud").is_err());
+        assert!(TransitionSetting::from_str("exploded").is_err());
+    }
+
+    #[test]
+    fn summaries() {
+        let bat = battery(BatteryStatus::NotCharging, 80, Some(80));
+        assert_eq!(
+            Transition::Limit.summary(&bat),
+            "Stopped charging at 80% limit"
+        );
+        assert_eq!(Transition::Unplugged.summary(&bat), "Unplugged: 80%");
+    }
+}
@@ -0,0 +1,194 @@
+use notify_rust::Urgency;
+use script_lib::battery::{CapacityLevel, Peripheral};
+use std::str::FromStr;
+
+/// A battery level below which to warn once, e.g. `10:critical:Battery critical`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarnLevel {
+    pub percent: f32,
+    pub urgency: Urgency,
+    pub text: String,
+}
+
+/// Parses `low`, `normal` or `critical`.
+pub fn parse_urgency(s: &str) -> Result<Urgency, String> {
+    match s.trim().to_lowercase().as_str() {
+        "low" => Ok(Urgency::Low),
+        "normal" => Ok(Urgency::Normal),
+        "critical" => Ok(Urgency::Critical),
+        u => Err(format!("unrecognized urgency: {u}")),
+    }
+}
+
+impl WarnLevel {
+    /// A critical "Battery low" level, as given by --warn-min.
+    pub fn at(percent: f32) -> Self {
+        Self {
+            percent,
+            urgency: Urgency::Critical,
+            text: "Battery low".to_owned(),
+        }
+    }
+}
+
+impl FromStr for WarnLevel {
+    type Err = String;
+
+    /// Parses `PERCENT[:URGENCY[:TEXT]]`; urgency defaults to critical.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let percent = parts
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .parse::<f32>()
+            .map_err(|_e| format!("invalid warning level: {s}"))?;
+        let urgency = match parts.next().map(str::trim) {
+            None | Some("") => Urgency::Critical,
+            Some(u) => parse_urgency(u)?,
+        };
+        let text = match parts.next() {
+            Some(text) if !text.is_empty() => text.to_owned(),
+            _ => "Battery low".to_owned(),
+        };
+        Ok(Self {
+            percent,
+            urgency,
+            text,
+        })
+    }
+}
+
+/// Re-arms levels the battery has climbed `hysteresis` points back above, then, if `discharging`, marks every level at or above `percent` as fired.
+///
+/// `fired` holds the [WarnLevel::percent] of each level that has already fired. Returns the most severe newly crossed level, if any.
+pub fn update<'l>(
+    levels: &'l [WarnLevel],
+    fired: &mut Vec<f32>,
+    percent: f32,
+    hysteresis: f32,
+    discharging: bool,
+) -> Option<&'l WarnLevel> {
+    fired.retain(|level| percent < level + hysteresis);
+    if !discharging {
+        return None;
+    }
+    let mut res: Option<&WarnLevel> = None;
+    for level in levels.iter().filter(|l| percent <= l.percent) {
+        if fired.contains(&level.percent) {
+            continue;
+        }
+        fired.push(level.percent);
+        if res.map_or(true, |r| level.percent < r.percent) {
+            res = Some(level);
+        }
+    }
+    res
+}
+
+/// The percentage to check a peripheral's warning levels against.
+///
+/// Without one, a low [CapacityLevel] crosses every level, and anything else re-arms them.
+pub fn peripheral_percent(peripheral: &Peripheral) -> f32 {
+    match (peripheral.capacity, peripheral.capacity_level) {
+        (Some(capacity), _) => capacity as f32,
+        (None, Some(CapacityLevel::Critical | CapacityLevel::Low)) => 0.0,
+        (None, _) => 100.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use script_lib::battery::BatteryStatus;
+
+    fn levels() -> Vec<WarnLevel> {
+        [
+            "20:normal",
+            "10:critical:Battery critical",
+            "5::Plug in now",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn parse() {
+        let levels = levels();
+        assert_eq!(levels[0].percent, 20.0);
+        assert_eq!(levels[0].urgency, Urgency::Normal);
+        assert_eq!(levels[0].text, "Battery low");
+        assert_eq!(levels[1].text, "Battery critical");
+        assert_eq!(levels[2].urgency, Urgency::Critical);
+        assert_eq!(levels[2].text, "Plug in now");
+        assert!(WarnLevel::from_str("20:loud").is_err());
+        assert!(WarnLevel::from_str("low").is_err());
+    }
+
+    #[test]
+    fn fires_once_per_level() {
+        let levels = levels();
+        let mut fired = Vec::new();
+        let mut step = |percent| update(&levels, &mut fired, percent, 2.0, true).map(|l| l.percent);
+        assert_eq!(step(25.0), None);
+        assert_eq!(step(19.0), Some(20.0));
+        assert_eq!(step(18.0), None);
+        assert_eq!(step(10.0), Some(10.0));
+        // skipping past a level only reports the most severe one
+        assert_eq!(step(3.0), Some(5.0));
+        assert_eq!(step(2.0), None);
+    }
+
+    #[test]
+    fn rearms_above_hysteresis() {
+        let levels = levels();
+        let mut fired = Vec::new();
+        assert!(update(&levels, &mut fired, 9.0, 2.0, true).is_some());
+        assert_eq!(fired, [20.0, 10.0]);
+        // charging back up past 10% isn't enough...
+        assert_eq!(update(&levels, &mut fired, 11.0, 2.0, false), None);
+        assert_eq!(fired, [20.0, 10.0]);
+        // ...until it's 2 points above
+        assert_eq!(update(&levels, &mut fired, 12.0, 2.0, false), None);
+        assert_eq!(fired, [20.0]);
+        assert_eq!(
+            update(&levels, &mut fired, 10.0, 2.0, true).map(|l| l.percent),
+            Some(10.0)
+        );
+    }
+
+    #[test]
+    fn quiet_while_charging() {
+        let levels = levels();
+        let mut fired = Vec::new();
+        assert_eq!(update(&levels, &mut fired, 4.0, 2.0, false), None);
+        assert!(fired.is_empty());
+    }
+
+    #[test]
+    fn peripherals_fire_once() {
+        let levels = levels();
+        let mut mouse = Peripheral {
+            name: "hidpp_battery_0".to_owned(),
+            status: BatteryStatus::Discharging,
+            capacity: None,
+            capacity_level: Some(CapacityLevel::Low),
+            manufacturer: None,
+            model_name: None,
+            serial_number: None,
+        };
+        let mut fired = Vec::new();
+        let mut check = |mouse: &Peripheral| {
+            update(&levels, &mut fired, peripheral_percent(mouse), 2.0, true).map(|l| l.percent)
+        };
+        // a low level without a percentage crosses every level at once
+        assert_eq!(check(&mouse), Some(5.0));
+        assert_eq!(check(&mouse), None);
+        mouse.capacity_level = Some(CapacityLevel::Normal);
+        assert_eq!(check(&mouse), None);
+        mouse.capacity = Some(15);
+        assert_eq!(check(&mouse), Some(20.0));
+        assert_eq!(check(&mouse), None);
+    }
+}
@@ -2,7 +2,10 @@ use clap::Parser;
 use console::{Color, Style, Term};
 use git2::Repository;
 
-use script_lib::{battery::Battery, log::init_fern};
+use script_lib::{
+    battery::{self, Battery, CombinedBattery},
+    log::init_fern,
+};
 use std::{io::Write, str::FromStr};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
@@ -42,9 +45,12 @@ pub struct Args {
     /// user home dir
     #[clap(long, env)]
     pub home: String,
-    /// if given, the batteries with which to call check-battery
+    /// batteries to display; defaults to every system battery
     #[clap(short, long, env = "PROMPT_BATTERY_LIST")]
     pub batteries: Option<Vec<String>>,
+    /// disable the battery display, even if batteries are given (e.g. through PROMPT_BATTERY_LIST)
+    #[clap(long)]
+    pub no_battery: bool,
     /// whether to display git information, if in a git repo
     #[clap(short = 'g', long)]
     pub with_git: bool,
@@ -130,19 +136,30 @@ fn main() {
         .unwrap();
     }
 
-    if let Some(batteries) = args.batteries.as_ref() {
-        let rem = batteries
-            .iter()
-            .filter_map(|name| Battery::from_name(name).ok())
-            .fold(0.0, |acc, b| acc + b.part_actual())
-            / batteries.len() as f32;
+    let batteries = match (args.no_battery, args.batteries.take()) {
+        (true, _) => None,
+        (false, Some(batteries)) => Some(batteries),
+        (false, None) => battery::battery_names().ok().filter(|b| !b.is_empty()),
+    };
+    let loaded: Vec<Battery> = batteries
+        .iter()
+        .flatten()
+        .filter_map(|name| Battery::from_name(name).ok())
+        .collect();
+    if let Some(combined) = CombinedBattery::new(&loaded) {
         write!(
             &mut term,
             "{} ",
             yellow.apply_to(format!(
-                "⚡{:.0}{}%",
-                rem * 100.0,
-                if args.zsh { "%" } else { "" }
+                "⚡{:.0}{}%{}",
+                combined.part_actual() * 100.0,
+                if args.zsh { "%" } else { "" },
+                combined
+                    .time_to_empty()
+                    .map_or_else(String::new, |dur| format!(
+                        " ({})",
+                        battery::format_duration(dur)
+                    ))
             ))
         )
         .unwrap();
@@ -7,6 +7,26 @@
 with builtins; let
   std = pkgs.lib;
   cfg = config.services.check-battery;
+  toml = pkgs.formats.toml {};
+  settings = lib.recursiveUpdate ({
+      notif-lvl = cfg.notificationLevel;
+      warn-min = cfg.warnMin;
+      warn-levels = cfg.warnLevels;
+      transitions = cfg.transitions;
+      peripherals = cfg.peripherals;
+      stop-min = cfg.stopMin;
+      stop-grace = cfg.stopGrace;
+      batteries = cfg.batteries;
+    }
+    // (
+      if isString cfg.stopAction
+      then {stop-action = cfg.stopAction;}
+      else {stop-command = cfg.stopAction.command;}
+    )
+    // lib.optionalAttrs (cfg.metricsPath != null) {metrics = cfg.metricsPath;})
+  cfg.settings;
+  configFile = toml.generate "check-battery.toml" settings;
+  args = "-l ${cfg.loggingLevel} -c ${configFile}";
 in {
   options.services.check-battery = with lib; {
     enable = mkEnableOption "battery level notifications";
@@ -20,10 +40,16 @@ in {
         type = types.str;
         default = "graphical-session.target";
       };
-      # batteries = mkOption {
-      #   type = types.listOf types.str;
-      #   default = [];
-      # };
+      daemon = mkOption {
+        type = types.bool;
+        description = "Run check-battery as a long-lived service polling every `interval`, instead of a oneshot service started by a timer. Critical notifications only get Hibernate/Suspend/Snooze buttons in this mode, since a oneshot run exits before they can be clicked.";
+        default = true;
+      };
+    };
+    batteries = mkOption {
+      type = types.listOf types.str;
+      description = "Batteries to check. If empty, every system battery is checked.";
+      default = [];
     };
     interval = mkOption {
       type = types.str;
@@ -45,37 +71,92 @@ in {
       description = "Minimum battery level below which to start sending warning notifications.";
       default = 20;
     };
+    warnLevels = mkOption {
+      type = types.listOf types.str;
+      description = "Escalating warning levels as PERCENT[:URGENCY[:TEXT]], each notified once when crossed. If empty, warnMin is used.";
+      default = [];
+      example = ["20:normal" "10:critical:Battery critical" "5:critical:Plug in now"];
+    };
+    transitions = mkOption {
+      type = types.listOf types.str;
+      description = "How to notify status transitions (charging, unplugged, limit, unknown) as TRANSITION[:URGENCY|off], at notificationLevel Info and above.";
+      default = [];
+      example = ["unplugged:critical" "charging:off"];
+    };
+    peripherals = mkOption {
+      type = types.bool;
+      description = "Whether to also warn about low peripheral batteries (wireless mice, keyboards, headsets...).";
+      default = false;
+    };
     stopMin = mkOption {
       type = types.ints.between 0.0 100.0;
       description = "Minimum battery level below which to hibernate the system.";
       default = 6;
     };
+    stopAction = mkOption {
+      type = types.either (types.enum ["hibernate" "suspend" "hybrid-sleep" "poweroff"]) (types.submodule {
+        options.command = mkOption {
+          type = types.str;
+          description = "Shell command to run instead.";
+        };
+      });
+      description = "What to do once the battery level drops below stopMin.";
+      default = "hibernate";
+    };
+    stopGrace = mkOption {
+      type = types.str;
+      description = "How long to count down before running stopAction. Plugging in during the countdown cancels it.";
+      default = "60s";
+    };
+    metricsPath = mkOption {
+      type = types.nullOr types.str;
+      description = "File to write battery metrics to as OpenMetrics gauges on every check, e.g. in node_exporter's textfile collector directory.";
+      default = null;
+      example = "/var/lib/node-exporter/textfile/battery.prom";
+    };
+    settings = mkOption {
+      type = toml.type;
+      description = "Extra settings for config.toml, merged over the ones above. Keys are named after the command line flags.";
+      default = {};
+      example = {
+        ignore = ["hidpp_battery_0"];
+        battery.BAT1 = {
+          name = "Slice battery";
+          warn-min = 30;
+        };
+      };
+    };
   };
   imports = [];
   config = lib.mkIf cfg.enable (lib.mkMerge [
     {
       home.packages = with pkgs; [cfg.package];
+      xdg.configFile."check-battery/config.toml".source = configFile;
     }
-    (lib.mkIf cfg.systemd.enable (lib.mkMerge [
+    (lib.mkIf (cfg.systemd.enable && cfg.systemd.daemon) {
+      systemd.user.services."check-battery" = {
+        Unit.Description = "battery level notifications";
+        Unit.PartOf = [cfg.systemd.target];
+        Service.ExecStart = "${cfg.package}/bin/check-battery ${args} --daemon --poll-interval ${cfg.interval}";
+        Service.Restart = "on-failure";
+        Install.WantedBy = [cfg.systemd.target];
+      };
+    })
+    (lib.mkIf (cfg.systemd.enable && !cfg.systemd.daemon) (lib.mkMerge [
       {
-        systemd.user.timers."check-battery@" = {
+        systemd.user.timers."check-battery" = {
           Unit.Description = "battery level notifications";
           Unit.PartOf = [cfg.systemd.target];
           Timer.OnUnitActiveSec = cfg.interval;
           Timer.OnActiveSec = "0s";
           Install.WantedBy = [cfg.systemd.target];
         };
-        systemd.user.services."check-battery@" = {
+        systemd.user.services."check-battery" = {
           Unit.PartOf = [cfg.systemd.target];
           Service.Type = "oneshot";
-          Service.ExecStart = "${cfg.package}/bin/check-battery -l ${cfg.loggingLevel} -n ${cfg.notificationLevel} -w ${toString cfg.warnMin} -s ${toString cfg.stopMin} %i";
+          Service.ExecStart = "${cfg.package}/bin/check-battery ${args}";
         };
       }
-      # {
-      #   systemd.user.services = std.genAttrs (map (bat: "check-battery@${bat}") cfg.systemd.batteries) {
-      #
-      #   };
-      # }
     ]))
   ]);
 }
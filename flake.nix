@@ -44,8 +44,10 @@
               src = ./.;
               copyLibs = true;
               copyBins = false;
-              cargoBuildOptions = base: base ++ ["--all-features"];
-              nativeBuildInputs = with final; [pkg-config openssl];
+              # every feature but the test-only dbus-mock
+              cargoBuildOptions = base: base ++ ["--features" "logging,notif,uevent,battery,upower,git"];
+              nativeBuildInputs = with final; [pkg-config];
+              buildInputs = with final; [dbus openssl];
               inherit SYSTEM_NOTIFICATION_ICON;
             };
         }
@@ -60,7 +62,8 @@
             src = ./.;
             targets = [bin];
             cargoBuildOptions = base: base ++ ["-p" bin];
-            nativeBuildInputs = with final; [pkg-config dbus openssl];
+            nativeBuildInputs = with final; [pkg-config];
+            buildInputs = with final; [dbus openssl];
             inherit SYSTEM_NOTIFICATION_ICON;
           })));
     in {
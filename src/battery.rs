@@ -1,18 +1,62 @@
+pub mod history;
+#[cfg(feature = "upower")]
+pub mod upower;
+
 use std::{
     fmt::Display,
-    fs::File,
-    io::{BufRead, BufReader, Read},
     path::{Path, PathBuf},
-    str::FromStr,
+    str::{FromStr, Utf8Error},
+    time::{Duration, Instant},
 };
 
-use lazy_static::lazy_static;
-use regex::Regex;
-
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::uevent::{KernelEvent, NetlinkSource, UEvent, UEventError, UEventSource};
 
 const ACPI_PATH: &str = "/sys/class/power_supply";
 
+#[derive(Debug, Error)]
+pub enum BatteryError {
+    #[error("missing field: {0}")]
+    MissingField(&'static str),
+    #[error("malformed uevent line: {0:?}")]
+    MalformedLine(String),
+    #[error("failed to parse {key}: {value:?}")]
+    Parse {
+        key: String,
+        value: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("invalid charge thresholds: {0}")]
+    InvalidThreshold(String),
+    #[error("{0} does not support charge thresholds")]
+    Unsupported(String),
+    #[error("permission denied writing {0:?} (setting charge thresholds usually requires root)")]
+    PermissionDenied(PathBuf),
+    #[error("the {0} backend is not available in this build")]
+    BackendUnavailable(BackendKind),
+    #[cfg(feature = "upower")]
+    #[error(transparent)]
+    DBus(#[from] dbus::Error),
+    #[error(transparent)]
+    UTF8(#[from] Utf8Error),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+impl From<UEventError> for BatteryError {
+    fn from(e: UEventError) -> Self {
+        match e {
+            UEventError::MalformedLine(line) => Self::MalformedLine(line),
+            UEventError::Parse { key, value, source } => Self::Parse { key, value, source },
+            UEventError::UTF8(e) => Self::UTF8(e),
+            UEventError::IO(e) => Self::IO(e),
+        }
+    }
+}
+
 #[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Copy, Clone, Debug)]
 pub enum BatteryStatus {
     Unknown,
@@ -49,12 +93,257 @@ impl FromStr for BatteryStatus {
     }
 }
 
-lazy_static! {
-    static ref REM_REG: Regex = Regex::new("POWER_SUPPLY_(?:ENERGY|CHARGE)_NOW=([0-9]+)").unwrap();
-    static ref FULL_REG: Regex =
-        Regex::new("POWER_SUPPLY_(?:ENERGY|CHARGE)_FULL=([0-9]+)").unwrap();
-    static ref FULL_DESIGN_REG: Regex =
-        Regex::new("POWER_SUPPLY_(?:ENERGY|CHARGE)_FULL_DESIGN=([0-9]+)").unwrap();
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Copy, Clone, Debug)]
+pub enum PowerSupplyType {
+    Unknown,
+    Battery,
+    Mains,
+    Usb,
+    Ups,
+    Wireless,
+}
+
+impl Display for PowerSupplyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PowerSupplyType::Unknown => "Unknown",
+            PowerSupplyType::Battery => "Battery",
+            PowerSupplyType::Mains => "Mains",
+            PowerSupplyType::Usb => "USB",
+            PowerSupplyType::Ups => "UPS",
+            PowerSupplyType::Wireless => "Wireless",
+        })
+    }
+}
+
+impl FromStr for PowerSupplyType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "unknown" => Ok(Self::Unknown),
+            "battery" => Ok(Self::Battery),
+            "mains" => Ok(Self::Mains),
+            // older kernels report the USB charger type (`USB_PD`, `USB_C`, ...) as the supply type
+            s if s == "usb" || s.starts_with("usb_") => Ok(Self::Usb),
+            "ups" => Ok(Self::Ups),
+            "wireless" => Ok(Self::Wireless),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Copy, Clone, Debug)]
+pub enum PowerSupplyScope {
+    Unknown,
+    /// Powers the machine itself
+    System,
+    /// Powers a peripheral, e.g. a wireless mouse
+    Device,
+}
+
+impl FromStr for PowerSupplyScope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "unknown" => Ok(Self::Unknown),
+            "system" => Ok(Self::System),
+            "device" => Ok(Self::Device),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct PowerSupply {
+    pub name: String,
+    pub kind: PowerSupplyType,
+    pub scope: PowerSupplyScope,
+    /// Whether an external supply is connected; `None` if the supply has no `online` attribute
+    pub online: Option<bool>,
+}
+
+impl PowerSupply {
+    /// Whether this supply provides power from outside the machine, rather than storing it.
+    pub fn is_external(&self) -> bool {
+        matches!(
+            self.kind,
+            PowerSupplyType::Mains | PowerSupplyType::Usb | PowerSupplyType::Wireless
+        )
+    }
+}
+
+/// Coarse charge level, as reported by devices that don't know their exact capacity.
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Copy, Clone, Debug)]
+pub enum CapacityLevel {
+    Unknown,
+    Critical,
+    Low,
+    Normal,
+    High,
+    Full,
+}
+
+impl Display for CapacityLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CapacityLevel::Unknown => "Unknown",
+            CapacityLevel::Critical => "Critical",
+            CapacityLevel::Low => "Low",
+            CapacityLevel::Normal => "Normal",
+            CapacityLevel::High => "High",
+            CapacityLevel::Full => "Full",
+        })
+    }
+}
+
+impl FromStr for CapacityLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "unknown" => Ok(Self::Unknown),
+            "critical" => Ok(Self::Critical),
+            "low" => Ok(Self::Low),
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            "full" => Ok(Self::Full),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The battery of a peripheral (`scope=Device`), e.g. a wireless mouse or headset.
+///
+/// Peripherals usually report only a percentage or a [CapacityLevel], so they can't be read as a [Battery].
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Peripheral {
+    pub name: String,
+    pub status: BatteryStatus,
+    /// Percent
+    pub capacity: Option<u8>,
+    pub capacity_level: Option<CapacityLevel>,
+    pub manufacturer: Option<String>,
+    pub model_name: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl Peripheral {
+    /// Builds a peripheral from the `POWER_SUPPLY_*` entries of its uevent file.
+    pub fn from_uevent(name: &str, uevent: &UEvent) -> Result<Self, BatteryError> {
+        let text = |key: &str| uevent.get(key).filter(|v| !v.is_empty()).map(str::to_owned);
+        Ok(Self {
+            name: name.to_owned(),
+            status: uevent
+                .get("POWER_SUPPLY_STATUS")
+                .and_then(|val| BatteryStatus::from_str(val).ok())
+                .unwrap_or(BatteryStatus::Unknown),
+            capacity: uevent.parse("POWER_SUPPLY_CAPACITY")?,
+            capacity_level: uevent
+                .get("POWER_SUPPLY_CAPACITY_LEVEL")
+                .map(|val| CapacityLevel::from_str(val).unwrap_or(CapacityLevel::Unknown)),
+            manufacturer: text("POWER_SUPPLY_MANUFACTURER"),
+            model_name: text("POWER_SUPPLY_MODEL_NAME"),
+            serial_number: text("POWER_SUPPLY_SERIAL_NUMBER"),
+        })
+    }
+
+    /// A human-readable name, e.g. `Logitech MX Master 3`.
+    pub fn display_name(&self) -> String {
+        match (&self.manufacturer, &self.model_name) {
+            (Some(manufacturer), Some(model)) => format!("{manufacturer} {model}"),
+            (None, Some(model)) => model.clone(),
+            _ => self.name.clone(),
+        }
+    }
+
+    /// Whether the battery is at or below `percent`, or reports a low [CapacityLevel] if it has no percentage.
+    pub fn is_low(&self, percent: f32) -> bool {
+        match (self.capacity, self.capacity_level) {
+            (Some(capacity), _) => capacity as f32 <= percent,
+            (None, Some(level)) => matches!(level, CapacityLevel::Critical | CapacityLevel::Low),
+            (None, None) => false,
+        }
+    }
+}
+
+/// Which family of uevent fields a battery's capacity was read from.
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Copy, Clone, Debug)]
+pub enum CapacityUnit {
+    /// `POWER_SUPPLY_ENERGY_*`, in µWh
+    Energy,
+    /// `POWER_SUPPLY_CHARGE_*`, in µAh
+    Charge,
+}
+
+impl Display for CapacityUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CapacityUnit::Energy => "Wh",
+            CapacityUnit::Charge => "Ah",
+        })
+    }
+}
+
+/// An amount of stored energy (Wh) or charge (Ah).
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+pub enum Capacity {
+    WattHours(f64),
+    AmpHours(f64),
+}
+
+impl Display for Capacity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.2}{}", self.value(), self.unit())
+    }
+}
+
+impl Capacity {
+    /// Wraps a raw sysfs value, in µWh or µAh.
+    pub fn from_micro(raw: u64, unit: CapacityUnit) -> Self {
+        let val = raw as f64 / 1_000_000.0;
+        match unit {
+            CapacityUnit::Energy => Self::WattHours(val),
+            CapacityUnit::Charge => Self::AmpHours(val),
+        }
+    }
+
+    pub fn unit(&self) -> CapacityUnit {
+        match self {
+            Self::WattHours(_) => CapacityUnit::Energy,
+            Self::AmpHours(_) => CapacityUnit::Charge,
+        }
+    }
+
+    /// The amount in Wh or Ah.
+    pub fn value(&self) -> f64 {
+        match *self {
+            Self::WattHours(val) | Self::AmpHours(val) => val,
+        }
+    }
+
+    /// Converts to `unit`, assuming the battery is at `voltage` volts; `None` if that takes a voltage and none was given.
+    pub fn to_unit(self, unit: CapacityUnit, voltage: Option<f64>) -> Option<Self> {
+        match (self, unit) {
+            (Self::WattHours(_), CapacityUnit::Energy)
+            | (Self::AmpHours(_), CapacityUnit::Charge) => Some(self),
+            (Self::AmpHours(ah), CapacityUnit::Energy) => voltage.map(|v| Self::WattHours(ah * v)),
+            (Self::WattHours(wh), CapacityUnit::Charge) => {
+                voltage.filter(|v| *v > 0.0).map(|v| Self::AmpHours(wh / v))
+            }
+        }
+    }
+
+    pub fn watt_hours(self, voltage: Option<f64>) -> Option<f64> {
+        self.to_unit(CapacityUnit::Energy, voltage)
+            .map(|c| c.value())
+    }
+
+    pub fn amp_hours(self, voltage: Option<f64>) -> Option<f64> {
+        self.to_unit(CapacityUnit::Charge, voltage)
+            .map(|c| c.value())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
@@ -64,132 +353,563 @@ pub struct Battery {
     pub actual_max: u32,
     pub factory_max: u32,
     pub status: BatteryStatus,
+    pub unit: CapacityUnit,
+    /// µW
+    pub power_now: Option<u32>,
+    /// µA
+    pub current_now: Option<u32>,
+    /// µV
+    pub voltage_now: Option<u32>,
+    /// µV
+    pub voltage_min_design: Option<u32>,
+    pub cycle_count: Option<u32>,
+    pub technology: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model_name: Option<String>,
+    pub serial_number: Option<String>,
+    /// Level, in percent, at which the firmware stops charging; see [ChargeThresholds]
+    pub charge_limit: Option<u8>,
+}
+
+/// How far a battery has charged; see [Battery::full_state].
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Copy, Clone, Debug)]
+pub enum FullState {
+    NotFull,
+    Full,
+    /// Charged up to its [Battery::charge_limit], and not charging any further
+    HeldAtLimit,
 }
 
-struct ByteLines<Base: Read> {
-    base: BufReader<Base>,
+impl Display for FullState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FullState::NotFull => "Not full",
+            FullState::Full => "Full",
+            FullState::HeldAtLimit => "Held at limit",
+        })
+    }
 }
 
-impl<Base: Read> Iterator for ByteLines<Base> {
-    type Item = std::io::Result<Vec<u8>>;
+/// Battery levels, in percent, between which the firmware keeps the battery charged.
+///
+/// Charging starts once the level drops below `start` and stops once it reaches `end`; either may be `None` if the battery doesn't expose it.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct ChargeThresholds {
+    pub start: Option<u8>,
+    pub end: Option<u8>,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut buf = Vec::new();
-        match self.base.read_until(b'\n', &mut buf) {
-            Err(e) => Some(Err(e)),
-            Ok(0) => None,
-            Ok(_i) => Some(Ok(buf)),
+impl ChargeThresholds {
+    /// Checks that both thresholds are percentages and that charging starts below where it stops.
+    pub fn validate(&self) -> Result<(), BatteryError> {
+        for (attr, val) in [("start", self.start), ("end", self.end)] {
+            if let Some(val) = val {
+                if val > 100 {
+                    return Err(BatteryError::InvalidThreshold(format!(
+                        "{attr} threshold {val}% is above 100%"
+                    )));
+                }
+            }
+        }
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if start >= end => Err(BatteryError::InvalidThreshold(
+                format!("start threshold {start}% must be below end threshold {end}%"),
+            )),
+            (_, Some(0)) => Err(BatteryError::InvalidThreshold(
+                "end threshold must be above 0%".to_owned(),
+            )),
+            _ => Ok(()),
         }
     }
 }
 
-impl<Base: Read> ByteLines<Base> {
-    pub fn new(base: Base) -> Self {
-        Self {
-            base: BufReader::new(base),
-        }
+const START_THRESHOLD_ATTR: &str = "charge_control_start_threshold";
+const END_THRESHOLD_ATTR: &str = "charge_control_end_threshold";
+
+/// Reads a single-line sysfs attribute, trimming the trailing newline.
+fn read_attr(path: &Path, attr: &str) -> std::io::Result<String> {
+    std::fs::read_to_string(path.join(attr)).map(|s| s.trim().to_owned())
+}
+
+/// A directory laid out like `/sys/class/power_supply`, from which batteries are read.
+///
+/// [PowerSupplySource::default] reads from [ACPI_PATH]; any other root (e.g. a directory of recorded uevent files) can be given with [PowerSupplySource::new].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PowerSupplySource {
+    root: PathBuf,
+}
+
+impl Default for PowerSupplySource {
+    fn default() -> Self {
+        Self::new(ACPI_PATH)
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct UEventEntryRaw(String, Vec<u8>);
+impl PowerSupplySource {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
 
-#[derive(Debug, Clone)]
-pub struct UEventEntry(String, String);
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
 
-impl Battery {
-    pub fn from_name(name: &str) -> std::io::Result<Self> {
-        let bat_path: PathBuf = Path::new(ACPI_PATH).join(name);
-        let uevent = match File::open(&bat_path.join("uevent")) {
-            Ok(f) => f,
+    /// Reads the `type`, `scope` and `online` attributes of a single power supply.
+    pub fn supply(&self, name: &str) -> std::io::Result<PowerSupply> {
+        let path = self.root.join(name);
+        let kind = PowerSupplyType::from_str(&read_attr(&path, "type")?)
+            .unwrap_or(PowerSupplyType::Unknown);
+        let scope = match read_attr(&path, "scope") {
+            Ok(scope) => PowerSupplyScope::from_str(&scope).unwrap_or(PowerSupplyScope::Unknown),
+            Err(_e) => PowerSupplyScope::Unknown,
+        };
+        let online = match read_attr(&path, "online") {
+            Ok(online) => Some(online != "0"),
+            Err(_e) => None,
+        };
+        Ok(PowerSupply {
+            name: name.to_owned(),
+            kind,
+            scope,
+            online,
+        })
+    }
+
+    /// Lists every power supply under this root, sorted by name.
+    pub fn supplies(&self) -> std::io::Result<Vec<PowerSupply>> {
+        let mut res = Vec::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            match self.supply(&name) {
+                Ok(supply) => res.push(supply),
+                Err(e) => log::warn!("Failed to read power supply {}: {:?}", name, e),
+            }
+        }
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(res)
+    }
+
+    /// Lists the names of all system batteries under this root, sorted by name.
+    ///
+    /// Peripheral batteries (`scope=Device`) are skipped.
+    pub fn battery_names(&self) -> std::io::Result<Vec<String>> {
+        Ok(self
+            .supplies()?
+            .into_iter()
+            .filter(|s| s.kind == PowerSupplyType::Battery && s.scope != PowerSupplyScope::Device)
+            .map(|s| s.name)
+            .collect())
+    }
+
+    /// Lists the names of all peripheral batteries (`scope=Device`) under this root, sorted by name.
+    pub fn peripheral_names(&self) -> std::io::Result<Vec<String>> {
+        Ok(self
+            .supplies()?
+            .into_iter()
+            .filter(|s| s.kind == PowerSupplyType::Battery && s.scope == PowerSupplyScope::Device)
+            .map(|s| s.name)
+            .collect())
+    }
+
+    pub fn peripheral(&self, name: &str) -> Result<Peripheral, BatteryError> {
+        Peripheral::from_uevent(name, &UEvent::from_device(&self.root.join(name))?)
+    }
+
+    /// Loads every peripheral found by [PowerSupplySource::peripheral_names], skipping those that fail to load.
+    pub fn peripherals(&self) -> std::io::Result<Vec<Peripheral>> {
+        Ok(self
+            .peripheral_names()?
+            .iter()
+            .filter_map(|name| match self.peripheral(name) {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    log::warn!("Failed to read peripheral {}: {}", name, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Whether any external supply (mains, USB or wireless) is online.
+    ///
+    /// Unlike [BatteryStatus], this doesn't depend on the battery firmware, which may report `Unknown` or `Not charging` while plugged in.
+    pub fn is_plugged_in(&self) -> std::io::Result<bool> {
+        Ok(self.supplies()?.iter().any(|s| {
+            s.is_external() && s.scope != PowerSupplyScope::Device && s.online == Some(true)
+        }))
+    }
+
+    /// Loads every system battery found by [PowerSupplySource::battery_names], skipping those that fail to load.
+    pub fn batteries(&self) -> std::io::Result<Vec<Battery>> {
+        Ok(self
+            .battery_names()?
+            .iter()
+            .filter_map(|name| match self.battery(name) {
+                Ok(b) => Some(b),
+                Err(e) => {
+                    log::warn!("Failed to read battery {}: {}", name, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    pub fn battery(&self, name: &str) -> Result<Battery, BatteryError> {
+        let bat_path: PathBuf = self.root.join(name);
+        let uevent = match UEvent::from_device(&bat_path) {
+            Ok(u) => u,
             Err(e) => {
                 log::error!(
-                    "Failed to open uevent file ({}/uevent) for {}: {:?}",
-                    bat_path.to_str().unwrap(),
+                    "Failed to read uevent file ({}/uevent) for {}: {:?}",
+                    bat_path.display(),
                     name,
                     e
                 );
-                return Err(e);
+                return Err(e.into());
             }
         };
-        let mut remaining = None;
-        let mut actual_max = None;
-        let mut factory_max = None;
-        let mut status = None;
-        for line_res in ByteLines::new(uevent) {
-            match line_res {
+        let mut battery = Battery::from_uevent(name, &uevent)?;
+        // drivers that add thresholds as extra attributes don't list them in the uevent file
+        if battery.charge_limit.is_none() {
+            battery.charge_limit = self
+                .read_threshold(name, END_THRESHOLD_ATTR)
+                .unwrap_or_else(|e| {
+                    log::warn!("Failed to read charge limit of {}: {}", name, e);
+                    None
+                });
+        }
+        Ok(battery)
+    }
+
+    /// Reads a threshold attribute; `None` if the battery doesn't have it.
+    fn read_threshold(&self, name: &str, attr: &'static str) -> Result<Option<u8>, BatteryError> {
+        match read_attr(&self.root.join(name), attr) {
+            Ok(val) => val.parse().map(Some).map_err(|e| BatteryError::Parse {
+                key: attr.to_owned(),
+                value: val,
+                source: Box::new(e),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_threshold(&self, name: &str, attr: &'static str, val: u8) -> Result<(), BatteryError> {
+        let path = self.root.join(name).join(attr);
+        if !path.exists() {
+            return Err(BatteryError::Unsupported(name.to_owned()));
+        }
+        std::fs::write(&path, val.to_string()).map_err(|e| match e.kind() {
+            std::io::ErrorKind::PermissionDenied => BatteryError::PermissionDenied(path),
+            // drivers reject values they can't apply with EINVAL
+            std::io::ErrorKind::InvalidInput => {
+                BatteryError::InvalidThreshold(format!("{name} rejected {attr}={val}"))
+            }
+            _ => e.into(),
+        })
+    }
+
+    /// Reads the charge thresholds of a battery.
+    pub fn charge_thresholds(&self, name: &str) -> Result<ChargeThresholds, BatteryError> {
+        // distinguish a missing battery from one without thresholds
+        std::fs::metadata(self.root.join(name))?;
+        Ok(ChargeThresholds {
+            start: self.read_threshold(name, START_THRESHOLD_ATTR)?,
+            end: self.read_threshold(name, END_THRESHOLD_ATTR)?,
+        })
+    }
+
+    /// Sets the charge thresholds of a battery, leaving those given as `None` unchanged; returns the new thresholds.
+    ///
+    /// The result is validated against the current thresholds before anything is written, and the thresholds are written in whichever order keeps start below end throughout, since drivers reject writes that would cross them.
+    pub fn set_charge_thresholds(
+        &self,
+        name: &str,
+        start: Option<u8>,
+        end: Option<u8>,
+    ) -> Result<ChargeThresholds, BatteryError> {
+        let current = self.charge_thresholds(name)?;
+        if (start.is_some() && current.start.is_none()) || (end.is_some() && current.end.is_none())
+        {
+            return Err(BatteryError::Unsupported(name.to_owned()));
+        }
+        let new = ChargeThresholds {
+            start: start.or(current.start),
+            end: end.or(current.end),
+        };
+        new.validate()?;
+        let lowering_end =
+            matches!((end, current.end), (Some(new_end), Some(cur)) if new_end < cur);
+        if lowering_end {
+            if let Some(start) = start {
+                self.write_threshold(name, START_THRESHOLD_ATTR, start)?;
+            }
+            if let Some(end) = end {
+                self.write_threshold(name, END_THRESHOLD_ATTR, end)?;
+            }
+        } else {
+            if let Some(end) = end {
+                self.write_threshold(name, END_THRESHOLD_ATTR, end)?;
+            }
+            if let Some(start) = start {
+                self.write_threshold(name, START_THRESHOLD_ATTR, start)?;
+            }
+        }
+        Ok(new)
+    }
+}
+
+/// Somewhere batteries can be read from, e.g. sysfs or UPower.
+pub trait BatteryBackend {
+    /// Lists the names of all system batteries, sorted by name.
+    fn battery_names(&self) -> Result<Vec<String>, BatteryError>;
+
+    fn battery(&self, name: &str) -> Result<Battery, BatteryError>;
+
+    /// Whether the machine is running on external power.
+    fn is_plugged_in(&self) -> Result<bool, BatteryError>;
+
+    /// Loads every system battery, skipping those that fail to load.
+    fn batteries(&self) -> Result<Vec<Battery>, BatteryError> {
+        Ok(self
+            .battery_names()?
+            .iter()
+            .filter_map(|name| match self.battery(name) {
+                Ok(b) => Some(b),
                 Err(e) => {
-                    log::error!(
-                        "Failed to read uevent file ({}/uevent) for {}: {:?}",
-                        bat_path.to_str().unwrap(),
-                        name,
-                        e
-                    );
-                    return Err(e);
+                    log::warn!("Failed to read battery {}: {}", name, e);
+                    None
                 }
-                Ok(line) => {
-                    let mut split = line.split(|b| *b == b'=');
-                    let key = std::str::from_utf8(split.next().unwrap()).unwrap();
-                    let mut val = || std::str::from_utf8(split.next().unwrap()).unwrap().trim();
-                    match key {
-                        "POWER_SUPPLY_STATUS" => {
-                            let val = val();
-                            status = Some(match BatteryStatus::from_str(val) {
-                                Ok(b) => b,
-                                Err(_e) => {
-                                    log::warn!(
-                                        "Unrecognized status ({}/status) for {}: {}",
-                                        bat_path.to_str().unwrap(),
-                                        name,
-                                        val
-                                    );
-                                    BatteryStatus::Unknown
-                                }
-                            });
-                        }
-                        "POWER_SUPPLY_ENERGY_NOW" | "POWER_SUPPLY_CHARGE_NOW" => {
-                            let val = val();
-                            remaining =
-                                Some(u32::from_str(val).unwrap_or_else(|_| {
-                                    panic!("Failed to read remaining: {}", val)
-                                }));
-                        }
-                        "POWER_SUPPLY_ENERGY_FULL" | "POWER_SUPPLY_CHARGE_FULL" => {
-                            let val = val();
-                            actual_max = Some(
-                                u32::from_str(val)
-                                    .unwrap_or_else(|_| panic!("Failed to read maximum: {}", val)),
-                            );
-                        }
-                        "POWER_SUPPLY_ENERGY_FULL_DESIGN" | "POWER_SUPPLY_CHARGE_FULL_DESIGN" => {
-                            let val = val();
-                            factory_max = Some(u32::from_str(val).unwrap_or_else(|_| {
-                                panic!("Failed to read factory maximum: {}", val)
-                            }));
-                        }
-                        _ => continue,
+            })
+            .collect())
+    }
+
+    /// Loads the batteries of every peripheral, e.g. wireless mice and keyboards.
+    fn peripherals(&self) -> Result<Vec<Peripheral>, BatteryError>;
+}
+
+impl BatteryBackend for PowerSupplySource {
+    fn battery_names(&self) -> Result<Vec<String>, BatteryError> {
+        Ok(PowerSupplySource::battery_names(self)?)
+    }
+
+    fn battery(&self, name: &str) -> Result<Battery, BatteryError> {
+        PowerSupplySource::battery(self, name)
+    }
+
+    fn is_plugged_in(&self) -> Result<bool, BatteryError> {
+        Ok(PowerSupplySource::is_plugged_in(self)?)
+    }
+
+    fn peripherals(&self) -> Result<Vec<Peripheral>, BatteryError> {
+        Ok(PowerSupplySource::peripherals(self)?)
+    }
+}
+
+/// Which [BatteryBackend] to read batteries from.
+#[derive(Eq, PartialEq, Hash, Serialize, Deserialize, Copy, Clone, Debug, Default)]
+pub enum BackendKind {
+    /// `/sys/class/power_supply`
+    #[default]
+    Sysfs,
+    /// `org.freedesktop.UPower`, over the system bus; requires the `upower` feature
+    UPower,
+}
+
+impl Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BackendKind::Sysfs => "sysfs",
+            BackendKind::UPower => "upower",
+        })
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sysfs" => Ok(Self::Sysfs),
+            "upower" => Ok(Self::UPower),
+            _ => Err(format!("unrecognized battery backend: {s}")),
+        }
+    }
+}
+
+impl BackendKind {
+    /// Connects to the backend.
+    pub fn open(self) -> Result<Box<dyn BatteryBackend>, BatteryError> {
+        match self {
+            BackendKind::Sysfs => Ok(Box::new(PowerSupplySource::default())),
+            #[cfg(feature = "upower")]
+            BackendKind::UPower => Ok(Box::new(upower::UPowerSource::new()?)),
+            #[cfg(not(feature = "upower"))]
+            BackendKind::UPower => Err(BatteryError::BackendUnavailable(self)),
+        }
+    }
+}
+
+/// A power supply being added, removed or changed, as reported by the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerSupplyEvent {
+    pub name: String,
+    /// `add`, `remove`, `change`, ...
+    pub action: String,
+    pub properties: UEvent,
+}
+
+impl PowerSupplyEvent {
+    fn from_kernel(event: KernelEvent) -> Option<Self> {
+        if event.subsystem() != Some("power_supply") {
+            return None;
+        }
+        let name = match event.properties.get("POWER_SUPPLY_NAME") {
+            Some(name) => name.to_owned(),
+            None => event.devpath.rsplit('/').next()?.to_owned(),
+        };
+        Some(Self {
+            name,
+            action: event.action,
+            properties: event.properties,
+        })
+    }
+
+    /// Reads the battery state carried by the event itself, without rereading sysfs.
+    pub fn battery(&self) -> Result<Battery, BatteryError> {
+        Battery::from_uevent(&self.name, &self.properties)
+    }
+}
+
+/// Filters a stream of kernel uevents down to power supply events.
+#[derive(Debug)]
+pub struct PowerSupplyWatcher<S: UEventSource> {
+    source: S,
+}
+
+impl<S: UEventSource> PowerSupplyWatcher<S> {
+    pub fn new(source: S) -> Self {
+        Self { source }
+    }
+
+    /// Waits up to `timeout` (or forever, if `None`) for the next power supply event; returns `None` on timeout.
+    ///
+    /// Messages that can't be decoded are logged and skipped.
+    pub fn recv(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<PowerSupplyEvent>, UEventError> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            match self.source.recv(remaining) {
+                Ok(None) => return Ok(None),
+                Ok(Some(event)) => {
+                    if let Some(event) = PowerSupplyEvent::from_kernel(event) {
+                        return Ok(Some(event));
                     }
                 }
+                // events of every subsystem come through, so one that can't be decoded is most likely unrelated
+                Err(e @ (UEventError::MalformedLine(_) | UEventError::UTF8(_))) => {
+                    log::warn!("Skipping undecodable uevent: {}", e)
+                }
+                Err(e) => return Err(e),
             }
-            if remaining.is_some()
-                && actual_max.is_some()
-                && factory_max.is_some()
-                && status.is_some()
-            {
-                break;
+            if remaining == Some(Duration::ZERO) {
+                return Ok(None);
             }
         }
-        let remaining = remaining.unwrap();
-        let actual_max = actual_max.unwrap();
-        let factory_max = factory_max.unwrap();
-        let status = status.unwrap();
+    }
+}
+
+/// Subscribes to power supply events from the kernel.
+pub fn watch() -> std::io::Result<PowerSupplyWatcher<NetlinkSource>> {
+    Ok(PowerSupplyWatcher::new(NetlinkSource::new()?))
+}
+
+/// Whether the machine is plugged in; see [PowerSupplySource::is_plugged_in].
+pub fn is_plugged_in() -> std::io::Result<bool> {
+    PowerSupplySource::default().is_plugged_in()
+}
+
+/// Lists the names of all system batteries in [ACPI_PATH]; see [PowerSupplySource::battery_names].
+pub fn battery_names() -> std::io::Result<Vec<String>> {
+    PowerSupplySource::default().battery_names()
+}
+
+/// Reads the charge thresholds of a battery in [ACPI_PATH]; see [PowerSupplySource::charge_thresholds].
+pub fn charge_thresholds(name: &str) -> Result<ChargeThresholds, BatteryError> {
+    PowerSupplySource::default().charge_thresholds(name)
+}
+
+/// Sets the charge thresholds of a battery in [ACPI_PATH]; see [PowerSupplySource::set_charge_thresholds].
+pub fn set_charge_thresholds(
+    name: &str,
+    start: Option<u8>,
+    end: Option<u8>,
+) -> Result<ChargeThresholds, BatteryError> {
+    PowerSupplySource::default().set_charge_thresholds(name, start, end)
+}
+
+impl Battery {
+    /// Loads every system battery in [ACPI_PATH], skipping those that fail to load.
+    pub fn all() -> std::io::Result<Vec<Self>> {
+        PowerSupplySource::default().batteries()
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, BatteryError> {
+        PowerSupplySource::default().battery(name)
+    }
+
+    /// Builds a battery from the `POWER_SUPPLY_*` entries of its uevent file.
+    pub fn from_uevent(name: &str, uevent: &UEvent) -> Result<Self, BatteryError> {
+        let unit = match uevent.contains_key("POWER_SUPPLY_CHARGE_NOW")
+            && !uevent.contains_key("POWER_SUPPLY_ENERGY_NOW")
+        {
+            true => CapacityUnit::Charge,
+            false => CapacityUnit::Energy,
+        };
+        // every capacity must be in the same unit, so there's no falling back to the other one
+        let capacity = |energy: &'static str, charge: &'static str| -> Result<u32, BatteryError> {
+            let key = match unit {
+                CapacityUnit::Energy => energy,
+                CapacityUnit::Charge => charge,
+            };
+            uevent.parse(key)?.ok_or(BatteryError::MissingField(key))
+        };
+        // some drivers report the present rate as a negative number while discharging
+        let rate = |key: &str| -> Result<Option<u32>, BatteryError> {
+            Ok(uevent.parse::<i32>(key)?.map(i32::unsigned_abs))
+        };
+        let text = |key: &str| uevent.get(key).filter(|v| !v.is_empty()).map(str::to_owned);
+        let status = match uevent.get("POWER_SUPPLY_STATUS") {
+            None => return Err(BatteryError::MissingField("POWER_SUPPLY_STATUS")),
+            Some(val) => BatteryStatus::from_str(val).unwrap_or_else(|_e| {
+                log::warn!("Unrecognized status for {}: {}", name, val);
+                BatteryStatus::Unknown
+            }),
+        };
 
         Ok(Self {
             name: name.to_owned(),
-            remaining,
-            actual_max,
-            factory_max,
+            remaining: capacity("POWER_SUPPLY_ENERGY_NOW", "POWER_SUPPLY_CHARGE_NOW")?,
+            actual_max: capacity("POWER_SUPPLY_ENERGY_FULL", "POWER_SUPPLY_CHARGE_FULL")?,
+            factory_max: capacity(
+                "POWER_SUPPLY_ENERGY_FULL_DESIGN",
+                "POWER_SUPPLY_CHARGE_FULL_DESIGN",
+            )?,
             status,
+            unit,
+            power_now: rate("POWER_SUPPLY_POWER_NOW")?,
+            current_now: rate("POWER_SUPPLY_CURRENT_NOW")?,
+            voltage_now: uevent.parse("POWER_SUPPLY_VOLTAGE_NOW")?,
+            voltage_min_design: uevent.parse("POWER_SUPPLY_VOLTAGE_MIN_DESIGN")?,
+            cycle_count: uevent.parse("POWER_SUPPLY_CYCLE_COUNT")?,
+            technology: text("POWER_SUPPLY_TECHNOLOGY"),
+            manufacturer: text("POWER_SUPPLY_MANUFACTURER"),
+            model_name: text("POWER_SUPPLY_MODEL_NAME"),
+            serial_number: text("POWER_SUPPLY_SERIAL_NUMBER"),
+            charge_limit: uevent.parse("POWER_SUPPLY_CHARGE_CONTROL_END_THRESHOLD")?,
         })
     }
 
@@ -201,9 +921,271 @@ impl Battery {
         self.remaining as f32 / self.factory_max as f32
     }
 
+    pub fn remaining_capacity(&self) -> Capacity {
+        Capacity::from_micro(self.remaining as u64, self.unit)
+    }
+
+    pub fn actual_max_capacity(&self) -> Capacity {
+        Capacity::from_micro(self.actual_max as u64, self.unit)
+    }
+
+    pub fn factory_max_capacity(&self) -> Capacity {
+        Capacity::from_micro(self.factory_max as u64, self.unit)
+    }
+
+    /// Voltage at which to convert between energy and charge, in volts.
+    ///
+    /// The design voltage is preferred, since the present voltage drifts with the charge level.
+    pub fn nominal_voltage(&self) -> Option<f64> {
+        self.voltage_min_design
+            .or(self.voltage_now)
+            .filter(|v| *v > 0)
+            .map(|v| v as f64 / 1_000_000.0)
+    }
+
+    /// Factor by which to multiply amounts in [Battery::unit] to get them in `unit`.
+    fn unit_factor(&self, unit: CapacityUnit) -> Option<f64> {
+        Capacity::from_micro(1_000_000, self.unit)
+            .to_unit(unit, self.nominal_voltage())
+            .map(|c| c.value())
+    }
+
+    /// Current full capacity as a fraction of the design capacity.
+    pub fn health(&self) -> f32 {
+        self.actual_max as f32 / self.factory_max as f32
+    }
+
+    /// Capacity lost since manufacture, as a fraction of the design capacity.
+    pub fn wear(&self) -> f32 {
+        1.0 - self.health()
+    }
+
+    /// Instantaneous power draw (or intake, while charging), in watts.
+    pub fn power_draw(&self) -> Option<f32> {
+        match (self.power_now, self.current_now, self.voltage_now) {
+            (Some(power), _, _) => Some(power as f32 / 1_000_000.0),
+            (None, Some(current), Some(voltage)) => {
+                Some((current as f64 * voltage as f64 / 1e12) as f32)
+            }
+            _ => None,
+        }
+    }
+
+    /// Present rate of change of [Battery::remaining], in its own unit per hour.
+    fn rate(&self) -> Option<f64> {
+        let rate = match (
+            self.unit,
+            self.power_now,
+            self.current_now,
+            self.voltage_now,
+        ) {
+            (CapacityUnit::Energy, Some(power), _, _) => power as f64,
+            (CapacityUnit::Energy, None, Some(current), Some(voltage)) => {
+                current as f64 * voltage as f64 / 1e6
+            }
+            (CapacityUnit::Charge, _, Some(current), _) => current as f64,
+            (CapacityUnit::Charge, Some(power), None, Some(voltage)) if voltage > 0 => {
+                power as f64 * 1e6 / voltage as f64
+            }
+            _ => return None,
+        };
+        Some(rate).filter(|r| *r > 0.0)
+    }
+
+    /// Estimated time until empty at the present rate, if discharging.
+    pub fn time_to_empty(&self) -> Option<Duration> {
+        if self.status != BatteryStatus::Discharging {
+            return None;
+        }
+        let rate = self.rate()?;
+        // a rate near zero gives a time too long for a Duration
+        Duration::try_from_secs_f64(self.remaining as f64 / rate * 3600.0).ok()
+    }
+
+    /// Estimated time until full at the present rate, if charging.
+    pub fn time_to_full(&self) -> Option<Duration> {
+        if self.status != BatteryStatus::Charging {
+            return None;
+        }
+        let rate = self.rate()?;
+        let missing = self.actual_max.saturating_sub(self.remaining);
+        Duration::try_from_secs_f64(missing as f64 / rate * 3600.0).ok()
+    }
+
+    /// Whether the battery has charged as far as it will, and why.
+    ///
+    /// `threshold` is the fraction of the target level (the [Battery::charge_limit], or 100%) past which a charging battery already counts as full.
+    pub fn full_state(&self, threshold: f32) -> FullState {
+        let limit = self
+            .charge_limit
+            .filter(|limit| *limit < 100)
+            .map(|limit| limit as f32 / 100.0);
+        match (self.status, limit) {
+            (BatteryStatus::Full, _) => FullState::Full,
+            // the firmware holds the battery anywhere between the start and end thresholds
+            (BatteryStatus::NotCharging, Some(_limit)) => FullState::HeldAtLimit,
+            (BatteryStatus::Charging, Some(limit)) if self.part_actual() >= limit * threshold => {
+                FullState::HeldAtLimit
+            }
+            (BatteryStatus::NotCharging, None) => FullState::Full,
+            (BatteryStatus::Charging, None) if self.part_actual() >= threshold => FullState::Full,
+            _ => FullState::NotFull,
+        }
+    }
+
+    /// Whether the battery is full or held at its charge limit; see [Battery::full_state].
     pub fn is_full(&self, threshold: f32) -> bool {
-        self.status == BatteryStatus::Full
-            || self.status == BatteryStatus::NotCharging
-            || (self.status == BatteryStatus::Charging && self.part_actual() >= threshold)
+        self.full_state(threshold) != FullState::NotFull
+    }
+}
+
+/// Several batteries treated as one, e.g. the internal and removable batteries of a dual-battery laptop.
+///
+/// Capacities are summed, so each battery counts in proportion to its size rather than equally.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CombinedBattery {
+    pub names: Vec<String>,
+    pub remaining: u64,
+    pub actual_max: u64,
+    pub factory_max: u64,
+    /// Unit of the summed capacities, in µWh or µAh
+    pub unit: CapacityUnit,
+    pub status: BatteryStatus,
+    /// Net rate at which [CombinedBattery::remaining] drops per hour (negative if it grows), if any charging or discharging battery reports one
+    rate: Option<f64>,
+    power_draw: Option<f32>,
+}
+
+impl CombinedBattery {
+    /// Combines the given batteries; `None` if there are none.
+    ///
+    /// Capacities are summed in Wh, converting charge-based batteries at their [Battery::nominal_voltage].
+    /// If some charge-based battery has no known voltage, they are summed in Ah instead; batteries that can't be converted to the chosen unit are left out.
+    pub fn new<'b>(batteries: impl IntoIterator<Item = &'b Battery>) -> Option<Self> {
+        let batteries: Vec<&Battery> = batteries.into_iter().collect();
+        let unit = match batteries
+            .iter()
+            .all(|b| b.unit_factor(CapacityUnit::Energy).is_some())
+        {
+            true => CapacityUnit::Energy,
+            false => CapacityUnit::Charge,
+        };
+        let batteries: Vec<(&Battery, f64)> = batteries
+            .into_iter()
+            .filter_map(|b| match b.unit_factor(unit) {
+                Some(factor) => Some((b, factor)),
+                None => {
+                    log::warn!(
+                        "Leaving {} out of the combined battery: cannot convert {} to {} without a voltage",
+                        b.name,
+                        b.unit,
+                        unit
+                    );
+                    None
+                }
+            })
+            .collect();
+        if batteries.is_empty() {
+            return None;
+        }
+        let statuses = || batteries.iter().map(|(b, _)| b.status);
+        let status = if statuses().any(|s| s == BatteryStatus::Discharging) {
+            BatteryStatus::Discharging
+        } else if statuses().any(|s| s == BatteryStatus::Charging) {
+            BatteryStatus::Charging
+        } else if statuses().all(|s| s == BatteryStatus::Full) {
+            BatteryStatus::Full
+        } else if statuses().all(|s| s == BatteryStatus::Full || s == BatteryStatus::NotCharging) {
+            BatteryStatus::NotCharging
+        } else {
+            BatteryStatus::Unknown
+        };
+        let sum = |val: fn(&Battery) -> u32| {
+            batteries
+                .iter()
+                .map(|(b, factor)| (val(b) as f64 * factor).round() as u64)
+                .sum()
+        };
+        let sum_opt = |vals: Vec<Option<f64>>| {
+            vals.iter()
+                .any(Option::is_some)
+                .then(|| vals.iter().flatten().sum::<f64>())
+        };
+        Some(Self {
+            names: batteries.iter().map(|(b, _)| b.name.clone()).collect(),
+            remaining: sum(|b| b.remaining),
+            actual_max: sum(|b| b.actual_max),
+            factory_max: sum(|b| b.factory_max),
+            unit,
+            status,
+            // a battery charging from another one offsets its drain
+            rate: sum_opt(
+                batteries
+                    .iter()
+                    .map(|(b, factor)| match b.status {
+                        BatteryStatus::Discharging => b.rate().map(|r| r * factor),
+                        BatteryStatus::Charging => b.rate().map(|r| -r * factor),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            power_draw: sum_opt(
+                batteries
+                    .iter()
+                    .map(|(b, _)| b.power_draw().map(f64::from))
+                    .collect(),
+            )
+            .map(|p| p as f32),
+        })
+    }
+
+    pub fn part_actual(&self) -> f32 {
+        self.remaining as f32 / self.actual_max as f32
+    }
+
+    pub fn part_factory(&self) -> f32 {
+        self.remaining as f32 / self.factory_max as f32
+    }
+
+    pub fn remaining_capacity(&self) -> Capacity {
+        Capacity::from_micro(self.remaining, self.unit)
+    }
+
+    pub fn actual_max_capacity(&self) -> Capacity {
+        Capacity::from_micro(self.actual_max, self.unit)
+    }
+
+    /// Total instantaneous power draw of all batteries, in watts.
+    pub fn power_draw(&self) -> Option<f32> {
+        self.power_draw
+    }
+
+    /// Estimated time until all batteries are empty at the present net rate, if discharging.
+    pub fn time_to_empty(&self) -> Option<Duration> {
+        if self.status != BatteryStatus::Discharging {
+            return None;
+        }
+        let rate = self.rate.filter(|r| *r > 0.0)?;
+        // a rate near zero gives a time too long for a Duration
+        Duration::try_from_secs_f64(self.remaining as f64 / rate * 3600.0).ok()
+    }
+
+    /// Estimated time until all batteries are full at the present net rate, if charging.
+    pub fn time_to_full(&self) -> Option<Duration> {
+        if self.status != BatteryStatus::Charging {
+            return None;
+        }
+        let rate = self.rate.map(|r| -r).filter(|r| *r > 0.0)?;
+        let missing = self.actual_max.saturating_sub(self.remaining);
+        Duration::try_from_secs_f64(missing as f64 / rate * 3600.0).ok()
+    }
+}
+
+/// Formats a duration as hours and minutes, e.g. `1h 23m`.
+pub fn format_duration(dur: Duration) -> String {
+    let mins = (dur.as_secs() + 30) / 60;
+    match (mins / 60, mins % 60) {
+        (0, m) => format!("{m}m"),
+        (h, m) => format!("{h}h {m}m"),
     }
 }
@@ -0,0 +1,108 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Battery, BatteryStatus, CapacityUnit};
+
+/// Seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A battery reading taken at a point in time.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Sample {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub remaining: u32,
+    pub actual_max: u32,
+    pub status: BatteryStatus,
+    pub unit: CapacityUnit,
+}
+
+impl Sample {
+    pub fn new(battery: &Battery, timestamp: u64) -> Self {
+        Self {
+            timestamp,
+            remaining: battery.remaining,
+            actual_max: battery.actual_max,
+            status: battery.status,
+            unit: battery.unit,
+        }
+    }
+
+    pub fn part_actual(&self) -> f32 {
+        self.remaining as f32 / self.actual_max as f32
+    }
+}
+
+/// Timestamped samples of a single battery, oldest first.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct History {
+    pub samples: VecDeque<Sample>,
+}
+
+impl History {
+    /// Appends a sample, ignoring it if it isn't newer than the latest one.
+    pub fn push(&mut self, sample: Sample) {
+        match self.samples.back() {
+            Some(last) if last.timestamp >= sample.timestamp => {}
+            _ => self.samples.push_back(sample),
+        }
+    }
+
+    /// Drops samples older than `max_age` before `now`.
+    pub fn prune(&mut self, now: u64, max_age: Duration) {
+        let cutoff = now.saturating_sub(max_age.as_secs());
+        while self.samples.front().is_some_and(|s| s.timestamp < cutoff) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Samples taken within `window` before `now`.
+    pub fn window(&self, now: u64, window: Duration) -> impl Iterator<Item = &Sample> {
+        let cutoff = now.saturating_sub(window.as_secs());
+        self.samples.iter().filter(move |s| s.timestamp >= cutoff)
+    }
+
+    /// Total change in [Sample::remaining] and time elapsed over consecutive pairs of samples in which the battery kept `status`.
+    fn spans(&self, now: u64, window: Duration, status: BatteryStatus) -> (f64, u64) {
+        let samples: Vec<&Sample> = self.window(now, window).collect();
+        samples
+            .windows(2)
+            .filter(|pair| {
+                pair[0].status == status && pair[1].status == status && pair[0].unit == pair[1].unit
+            })
+            .fold((0.0, 0), |(delta, secs), pair| {
+                (
+                    delta + pair[1].remaining as f64 - pair[0].remaining as f64,
+                    secs + (pair[1].timestamp - pair[0].timestamp),
+                )
+            })
+    }
+
+    /// Average discharge rate within `window` before `now`, in the battery's unit per hour (µW or µA).
+    ///
+    /// Only spans where the battery was discharging count; `None` if there were none.
+    pub fn average_discharge_rate(&self, now: u64, window: Duration) -> Option<f64> {
+        match self.spans(now, window, BatteryStatus::Discharging) {
+            (_, 0) => None,
+            (delta, secs) => Some(-delta / secs as f64 * 3600.0),
+        }
+    }
+
+    /// Average charge rate within `window` before `now`, in the battery's unit per hour (µW or µA).
+    ///
+    /// Only spans where the battery was charging count; `None` if there were none.
+    pub fn average_charge_rate(&self, now: u64, window: Duration) -> Option<f64> {
+        match self.spans(now, window, BatteryStatus::Charging) {
+            (_, 0) => None,
+            (delta, secs) => Some(delta / secs as f64 * 3600.0),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use dbus::{
+    arg::{prop_cast, PropMap},
+    blocking::{stdintf::org_freedesktop_dbus::Properties, Connection, Proxy},
+    Path,
+};
+
+use super::{
+    Battery, BatteryBackend, BatteryError, BatteryStatus, CapacityLevel, CapacityUnit, Peripheral,
+};
+
+pub const BUS_NAME: &str = "org.freedesktop.UPower";
+pub const UPOWER_PATH: &str = "/org/freedesktop/UPower";
+const UPOWER_IFACE: &str = "org.freedesktop.UPower";
+const DEVICE_IFACE: &str = "org.freedesktop.UPower.Device";
+
+/// `Type` of a UPower device
+const TYPE_LINE_POWER: u32 = 1;
+const TYPE_BATTERY: u32 = 2;
+
+/// Reads batteries from the UPower daemon, for machines where sysfs isn't accessible.
+pub struct UPowerSource {
+    conn: Connection,
+    timeout: Duration,
+}
+
+impl UPowerSource {
+    /// Connects to UPower on the system bus.
+    pub fn new() -> Result<Self, dbus::Error> {
+        Ok(Self::with_connection(Connection::new_system()?))
+    }
+
+    /// Talks to whichever `org.freedesktop.UPower` is reachable over `conn`, e.g. a mock service on a private bus.
+    pub fn with_connection(conn: Connection) -> Self {
+        Self {
+            conn,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    fn proxy<'p>(&'p self, path: impl Into<Path<'p>>) -> Proxy<'p, &'p Connection> {
+        self.conn.with_proxy(BUS_NAME, path, self.timeout)
+    }
+
+    /// The properties of every device known to UPower, keyed by name.
+    fn devices(&self) -> Result<Vec<(String, PropMap)>, BatteryError> {
+        let (paths,): (Vec<Path<'static>>,) =
+            self.proxy(UPOWER_PATH)
+                .method_call(UPOWER_IFACE, "EnumerateDevices", ())?;
+        let mut res = Vec::new();
+        for path in paths {
+            let props = self.proxy(path).get_all(DEVICE_IFACE)?;
+            // UPower reports the sysfs name for kernel devices, but a full path for some others
+            let name = prop_cast::<String>(&props, "NativePath")
+                .and_then(|p| p.rsplit('/').next())
+                .unwrap_or_default()
+                .to_owned();
+            res.push((name, props));
+        }
+        res.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(res)
+    }
+}
+
+impl BatteryBackend for UPowerSource {
+    /// Lists batteries that power the machine; peripherals are skipped.
+    fn battery_names(&self) -> Result<Vec<String>, BatteryError> {
+        Ok(self
+            .devices()?
+            .into_iter()
+            .filter(|(_, props)| {
+                prop_cast::<u32>(props, "Type") == Some(&TYPE_BATTERY)
+                    && prop_cast::<bool>(props, "PowerSupply") == Some(&true)
+            })
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    fn battery(&self, name: &str) -> Result<Battery, BatteryError> {
+        match self.devices()?.into_iter().find(|(n, _)| n == name) {
+            Some((name, props)) => battery_from_props(&name, &props),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("UPower has no device named {name}"),
+            )
+            .into()),
+        }
+    }
+
+    /// Loads every battery-powered device that isn't a power supply of the machine itself.
+    fn peripherals(&self) -> Result<Vec<Peripheral>, BatteryError> {
+        Ok(self
+            .devices()?
+            .into_iter()
+            .filter(|(_, props)| {
+                prop_cast::<u32>(props, "Type").is_some_and(|t| *t != TYPE_LINE_POWER)
+                    && prop_cast::<bool>(props, "PowerSupply") == Some(&false)
+            })
+            .map(|(name, props)| peripheral_from_props(&name, &props))
+            .collect())
+    }
+
+    /// Whether any line power device is online.
+    fn is_plugged_in(&self) -> Result<bool, BatteryError> {
+        Ok(self.devices()?.iter().any(|(_, props)| {
+            prop_cast::<u32>(props, "Type") == Some(&TYPE_LINE_POWER)
+                && prop_cast::<bool>(props, "Online") == Some(&true)
+        }))
+    }
+}
+
+fn status_from_props(props: &PropMap) -> Option<BatteryStatus> {
+    Some(match prop_cast::<u32>(props, "State")? {
+        1 => BatteryStatus::Charging,
+        // 3 = empty
+        2 | 3 => BatteryStatus::Discharging,
+        4 => BatteryStatus::Full,
+        // 5 = pending charge, 6 = pending discharge
+        5 | 6 => BatteryStatus::NotCharging,
+        _ => BatteryStatus::Unknown,
+    })
+}
+
+fn text_from_props(props: &PropMap, key: &str) -> Option<String> {
+    prop_cast::<String>(props, key)
+        .filter(|v| !v.is_empty())
+        .cloned()
+}
+
+/// Builds a peripheral from the properties of an `org.freedesktop.UPower.Device`.
+pub fn peripheral_from_props(name: &str, props: &PropMap) -> Peripheral {
+    let capacity_level = match prop_cast::<u32>(props, "BatteryLevel") {
+        Some(3) => Some(CapacityLevel::Low),
+        Some(4) => Some(CapacityLevel::Critical),
+        Some(6) => Some(CapacityLevel::Normal),
+        Some(7) => Some(CapacityLevel::High),
+        Some(8) => Some(CapacityLevel::Full),
+        // 0 = unknown, 1 = none (the device reports a percentage instead)
+        _ => None,
+    };
+    Peripheral {
+        name: name.to_owned(),
+        status: status_from_props(props).unwrap_or(BatteryStatus::Unknown),
+        // UPower makes up a percentage for devices that only report a coarse level
+        capacity: match capacity_level {
+            Some(_) => None,
+            None => prop_cast::<f64>(props, "Percentage").map(|p| p.round() as u8),
+        },
+        capacity_level,
+        manufacturer: text_from_props(props, "Vendor"),
+        model_name: text_from_props(props, "Model"),
+        serial_number: text_from_props(props, "Serial"),
+    }
+}
+
+/// Builds a battery from the properties of an `org.freedesktop.UPower.Device`.
+///
+/// UPower always reports capacities as energy, converting charge-based batteries itself.
+pub fn battery_from_props(name: &str, props: &PropMap) -> Result<Battery, BatteryError> {
+    let micro =
+        |key: &'static str| prop_cast::<f64>(props, key).map(|v| (v * 1_000_000.0).round() as u32);
+    let required = |key: &'static str| micro(key).ok_or(BatteryError::MissingField(key));
+    let text = |key: &str| text_from_props(props, key);
+    let status = status_from_props(props).ok_or(BatteryError::MissingField("State"))?;
+    let technology = match prop_cast::<u32>(props, "Technology") {
+        Some(1) => Some("Li-ion"),
+        Some(2) => Some("Li-poly"),
+        Some(3) => Some("LiFePO4"),
+        Some(4) => Some("Lead acid"),
+        Some(5) => Some("NiCd"),
+        Some(6) => Some("NiMH"),
+        _ => None,
+    };
+    Ok(Battery {
+        name: name.to_owned(),
+        remaining: required("Energy")?,
+        actual_max: required("EnergyFull")?,
+        factory_max: required("EnergyFullDesign")?,
+        status,
+        unit: CapacityUnit::Energy,
+        power_now: micro("EnergyRate").filter(|r| *r > 0),
+        current_now: None,
+        voltage_now: micro("Voltage").filter(|v| *v > 0),
+        voltage_min_design: None,
+        cycle_count: prop_cast::<i32>(props, "ChargeCycles").and_then(|c| u32::try_from(*c).ok()),
+        technology: technology.map(str::to_owned),
+        manufacturer: text("Vendor"),
+        model_name: text("Model"),
+        serial_number: text("Serial"),
+        charge_limit: match prop_cast::<bool>(props, "ChargeThresholdEnabled") {
+            Some(true) => {
+                prop_cast::<u32>(props, "ChargeEndThreshold").and_then(|t| u8::try_from(*t).ok())
+            }
+            _ => None,
+        },
+    })
+}
@@ -0,0 +1,55 @@
+use dbus::{blocking::Connection, channel::Channel};
+use dbus_crossroads::Crossroads;
+use std::io::{self, BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+/// A private `dbus-daemon` to serve mock services on in tests, killed on drop.
+pub struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    /// Starts a `dbus-daemon` listening on a new address.
+    pub fn start() -> io::Result<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address)?;
+        Ok(Self {
+            daemon,
+            address: address.trim().to_owned(),
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Opens a new connection to this bus.
+    pub fn connect(&self) -> Result<Connection, dbus::Error> {
+        let mut channel = Channel::open_private(&self.address)?;
+        channel.register()?;
+        Ok(channel.into())
+    }
+
+    /// Serves `cr` under `name` on its own thread.
+    ///
+    /// Returns once `name` is owned, so clients can call it right away.
+    pub fn serve(&self, name: &str, cr: Crossroads) -> Result<(), dbus::Error> {
+        let conn = self.connect()?;
+        conn.request_name(name, false, true, false)?;
+        std::thread::spawn(move || cr.serve(&conn));
+        Ok(())
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
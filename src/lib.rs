@@ -1,8 +1,12 @@
 #[cfg(feature = "battery")]
 pub mod battery;
+#[cfg(feature = "dbus-mock")]
+pub mod dbus_mock;
 #[cfg(feature = "git")]
 pub mod git;
 #[cfg(feature = "logging")]
 pub mod log;
 #[cfg(feature = "notif")]
 pub mod notif;
+#[cfg(feature = "uevent")]
+pub mod uevent;
@@ -0,0 +1,297 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error as StdError,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+    str::{FromStr, Utf8Error},
+    time::Duration,
+};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum UEventError {
+    #[error("malformed uevent line: {0:?}")]
+    MalformedLine(String),
+    #[error("failed to parse {key}: {value:?}")]
+    Parse {
+        key: String,
+        value: String,
+        #[source]
+        source: Box<dyn StdError + Send + Sync>,
+    },
+    #[error(transparent)]
+    UTF8(#[from] Utf8Error),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+struct ByteLines<Base: Read> {
+    base: BufReader<Base>,
+}
+
+impl<Base: Read> Iterator for ByteLines<Base> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = Vec::new();
+        match self.base.read_until(b'\n', &mut buf) {
+            Err(e) => Some(Err(e)),
+            Ok(0) => None,
+            Ok(_i) => Some(Ok(buf)),
+        }
+    }
+}
+
+impl<Base: Read> ByteLines<Base> {
+    pub fn new(base: Base) -> Self {
+        Self {
+            base: BufReader::new(base),
+        }
+    }
+}
+
+/// A `KEY=value` entry as read, before the value is decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UEventEntryRaw(pub String, pub Vec<u8>);
+
+impl UEventEntryRaw {
+    /// Splits a line at its first `=`, trimming surrounding whitespace from the value.
+    pub fn from_line(line: &[u8]) -> Result<Self, UEventError> {
+        let i = line.iter().position(|b| *b == b'=').ok_or_else(|| {
+            UEventError::MalformedLine(String::from_utf8_lossy(line).trim().to_owned())
+        })?;
+        let key = std::str::from_utf8(&line[..i])?.trim().to_owned();
+        Ok(Self(key, trim_ascii(&line[i + 1..]).to_vec()))
+    }
+}
+
+/// `<[u8]>::trim_ascii`, which needs a newer rustc than we support.
+fn trim_ascii(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |i| i + 1);
+    &bytes[start..end]
+}
+
+/// A decoded `KEY=value` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UEventEntry(pub String, pub String);
+
+impl TryFrom<UEventEntryRaw> for UEventEntry {
+    type Error = UEventError;
+
+    fn try_from(UEventEntryRaw(key, val): UEventEntryRaw) -> Result<Self, Self::Error> {
+        match String::from_utf8(val) {
+            Ok(val) => Ok(Self(key, val)),
+            Err(e) => Err(e.utf8_error().into()),
+        }
+    }
+}
+
+/// The key/value pairs of a sysfs `uevent` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UEvent {
+    entries: HashMap<String, String>,
+}
+
+impl FromIterator<UEventEntry> for UEvent {
+    fn from_iter<I: IntoIterator<Item = UEventEntry>>(iter: I) -> Self {
+        Self {
+            entries: iter
+                .into_iter()
+                .map(|UEventEntry(key, val)| (key, val))
+                .collect(),
+        }
+    }
+}
+
+impl UEvent {
+    /// Parses newline-separated `KEY=value` lines, skipping blank ones.
+    pub fn from_reader(reader: impl Read) -> Result<Self, UEventError> {
+        ByteLines::new(reader)
+            .filter(|line| {
+                line.as_ref()
+                    .map_or(true, |l| !l.iter().all(u8::is_ascii_whitespace))
+            })
+            .map(|line| UEventEntryRaw::from_line(&line?)?.try_into())
+            .collect()
+    }
+
+    /// Parses NUL-separated `KEY=value` entries, as sent over netlink.
+    pub fn from_nul_separated(bytes: &[u8]) -> Result<Self, UEventError> {
+        bytes
+            .split(|b| *b == 0)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| UEventEntryRaw::from_line(entry)?.try_into())
+            .collect()
+    }
+
+    /// Reads the `uevent` file of a sysfs device directory.
+    pub fn from_device(path: &Path) -> Result<Self, UEventError> {
+        Self::from_reader(File::open(path.join("uevent"))?)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Parses the value of `key`, if present.
+    pub fn parse<T>(&self, key: &str) -> Result<Option<T>, UEventError>
+    where
+        T: FromStr,
+        T::Err: StdError + Send + Sync + 'static,
+    {
+        self.get(key)
+            .map(|val| {
+                T::from_str(val).map_err(|e| UEventError::Parse {
+                    key: key.to_owned(),
+                    value: val.to_owned(),
+                    source: Box::new(e),
+                })
+            })
+            .transpose()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A uevent broadcast by the kernel when a device is added, removed or changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelEvent {
+    /// `add`, `remove`, `change`, ...
+    pub action: String,
+    /// Path of the device relative to `/sys`
+    pub devpath: String,
+    pub properties: UEvent,
+}
+
+impl KernelEvent {
+    /// Parses a kobject-uevent netlink message: an `action@devpath` header followed by NUL-separated properties.
+    pub fn from_message(msg: &[u8]) -> Result<Self, UEventError> {
+        let header_len = msg.iter().position(|b| *b == 0).unwrap_or(msg.len());
+        let header = std::str::from_utf8(&msg[..header_len])?;
+        let (action, devpath) = header
+            .split_once('@')
+            .ok_or_else(|| UEventError::MalformedLine(header.to_owned()))?;
+        Ok(Self {
+            action: action.to_owned(),
+            devpath: devpath.to_owned(),
+            properties: UEvent::from_nul_separated(&msg[header_len..])?,
+        })
+    }
+
+    pub fn subsystem(&self) -> Option<&str> {
+        self.properties.get("SUBSYSTEM")
+    }
+}
+
+/// A stream of kernel uevents.
+pub trait UEventSource {
+    /// Waits up to `timeout` (or forever, if `None`) for the next event; returns `None` on timeout.
+    fn recv(&mut self, timeout: Option<Duration>) -> Result<Option<KernelEvent>, UEventError>;
+}
+
+/// Replays queued events, then times out forever after; mostly useful for tests.
+impl UEventSource for VecDeque<KernelEvent> {
+    fn recv(&mut self, _timeout: Option<Duration>) -> Result<Option<KernelEvent>, UEventError> {
+        Ok(self.pop_front())
+    }
+}
+
+/// Replays queued events and decoding errors, then times out forever after; mostly useful for tests.
+impl UEventSource for VecDeque<Result<KernelEvent, UEventError>> {
+    fn recv(&mut self, _timeout: Option<Duration>) -> Result<Option<KernelEvent>, UEventError> {
+        self.pop_front().transpose()
+    }
+}
+
+/// Receives uevents from the kernel over a `NETLINK_KOBJECT_UEVENT` socket.
+#[derive(Debug)]
+pub struct NetlinkSource {
+    fd: OwnedFd,
+    buf: Vec<u8>,
+}
+
+impl NetlinkSource {
+    /// Opens a socket subscribed to the kernel's uevent multicast group.
+    pub fn new() -> std::io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = 1;
+        let res = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd,
+            buf: vec![0; 8192],
+        })
+    }
+}
+
+impl UEventSource for NetlinkSource {
+    fn recv(&mut self, timeout: Option<Duration>) -> Result<Option<KernelEvent>, UEventError> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+        match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+            0 => return Ok(None),
+            res if res < 0 => return Err(std::io::Error::last_os_error().into()),
+            _ => {}
+        }
+        let len = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                self.buf.as_mut_ptr() as *mut libc::c_void,
+                self.buf.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        KernelEvent::from_message(&self.buf[..len as usize]).map(Some)
+    }
+}
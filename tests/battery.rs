@@ -0,0 +1,513 @@
+use script_lib::{
+    battery::{
+        format_duration,
+        history::{History, Sample},
+        Battery, BatteryError, BatteryStatus, Capacity, CapacityLevel, CapacityUnit,
+        ChargeThresholds, CombinedBattery, FullState, Peripheral, PowerSupply, PowerSupplyScope,
+        PowerSupplySource, PowerSupplyType, PowerSupplyWatcher,
+    },
+    uevent::KernelEvent,
+};
+use std::{collections::VecDeque, time::Duration};
+
+fn fixtures() -> PowerSupplySource {
+    PowerSupplySource::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/power_supply"
+    ))
+}
+
+#[test]
+fn battery_names_skips_mains_and_devices() {
+    assert_eq!(
+        fixtures().battery_names().unwrap(),
+        ["BAT0", "BAT1", "BAT2", "BATT", "CMB0"]
+    );
+}
+
+#[test]
+fn supplies() {
+    let supplies = fixtures().supplies().unwrap();
+    assert_eq!(supplies.len(), 9);
+    assert_eq!(
+        supplies[0],
+        PowerSupply {
+            name: "AC".to_owned(),
+            kind: PowerSupplyType::Mains,
+            scope: PowerSupplyScope::Unknown,
+            online: Some(true),
+        }
+    );
+    let usbc = fixtures().supply("ucsi-source-psy-USBC000:001").unwrap();
+    assert_eq!(usbc.kind, PowerSupplyType::Usb);
+    assert_eq!(usbc.online, Some(false));
+    assert!(usbc.is_external());
+    let mouse = fixtures().supply("hidpp_battery_0").unwrap();
+    assert_eq!(mouse.scope, PowerSupplyScope::Device);
+    assert!(!mouse.is_external());
+}
+
+#[test]
+fn plugged_in() {
+    assert!(fixtures().is_plugged_in().unwrap());
+    let unplugged = PowerSupplySource::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/unplugged"
+    ));
+    assert!(!unplugged.is_plugged_in().unwrap());
+    assert_eq!(
+        unplugged.battery("BAT0").unwrap().status,
+        BatteryStatus::Unknown
+    );
+}
+
+#[test]
+fn energy_based() {
+    assert_eq!(
+        fixtures().battery("BAT0").unwrap(),
+        Battery {
+            name: "BAT0".to_owned(),
+            remaining: 33150000,
+            actual_max: 51270000,
+            factory_max: 57000000,
+            status: BatteryStatus::Discharging,
+            unit: CapacityUnit::Energy,
+            power_now: Some(7832000),
+            current_now: None,
+            voltage_now: Some(16213000),
+            voltage_min_design: Some(15440000),
+            cycle_count: Some(312),
+            technology: Some("Li-poly".to_owned()),
+            manufacturer: Some("SMP".to_owned()),
+            model_name: Some("5B10W13975".to_owned()),
+            serial_number: Some("1234".to_owned()),
+            charge_limit: Some(80),
+        }
+    );
+}
+
+#[test]
+fn charge_based() {
+    assert_eq!(
+        fixtures().battery("BAT1").unwrap(),
+        Battery {
+            name: "BAT1".to_owned(),
+            remaining: 2140000,
+            actual_max: 3964000,
+            factory_max: 4210000,
+            status: BatteryStatus::Charging,
+            unit: CapacityUnit::Charge,
+            power_now: None,
+            current_now: Some(1523000),
+            voltage_now: Some(12486000),
+            voltage_min_design: Some(11400000),
+            cycle_count: Some(0),
+            technology: Some("Li-ion".to_owned()),
+            manufacturer: Some("SWD-ATL3.618".to_owned()),
+            model_name: Some("DELL 7FHHV".to_owned()),
+            serial_number: Some("4095".to_owned()),
+            charge_limit: None,
+        }
+    );
+}
+
+#[test]
+fn health_and_wear() {
+    let bat = fixtures().battery("BAT0").unwrap();
+    assert!((bat.health() - 0.8995).abs() < 0.001);
+    assert!((bat.wear() - 0.1005).abs() < 0.001);
+    let bat = fixtures().battery("BATT").unwrap();
+    assert_eq!(bat.cycle_count, None);
+    assert_eq!(bat.model_name, None);
+}
+
+#[test]
+fn time_to_empty_from_power() {
+    let bat = fixtures().battery("BAT0").unwrap();
+    assert!((bat.power_draw().unwrap() - 7.832).abs() < 0.001);
+    assert_eq!(bat.time_to_full(), None);
+    assert_eq!(format_duration(bat.time_to_empty().unwrap()), "4h 14m");
+}
+
+#[test]
+fn time_to_full_from_current() {
+    let bat = fixtures().battery("BAT1").unwrap();
+    assert!((bat.power_draw().unwrap() - 19.016).abs() < 0.001);
+    assert_eq!(bat.time_to_empty(), None);
+    assert_eq!(format_duration(bat.time_to_full().unwrap()), "1h 12m");
+}
+
+#[test]
+fn no_estimate_without_rate() {
+    let bat = fixtures().battery("BATT").unwrap();
+    assert_eq!(bat.power_draw(), None);
+    assert_eq!(bat.time_to_empty(), None);
+    assert_eq!(bat.time_to_full(), None);
+}
+
+#[test]
+fn status_case_and_whitespace() {
+    let bat = fixtures().battery("BATT").unwrap();
+    assert_eq!(bat.status, BatteryStatus::NotCharging);
+    assert!((bat.part_actual() - 0.8).abs() < 0.001);
+}
+
+#[test]
+fn unrecognized_status_is_unknown() {
+    assert_eq!(
+        fixtures().battery("BAT2").unwrap().status,
+        BatteryStatus::Unknown
+    );
+}
+
+#[test]
+fn missing_fields() {
+    assert!(matches!(
+        fixtures().battery("CMB0"),
+        Err(BatteryError::MissingField(
+            "POWER_SUPPLY_ENERGY_FULL_DESIGN"
+        ))
+    ));
+}
+
+#[test]
+fn missing_battery() {
+    assert!(matches!(
+        fixtures().battery("BAT9"),
+        Err(BatteryError::IO(_))
+    ));
+}
+
+#[test]
+fn batteries_skips_failures() {
+    let names: Vec<_> = fixtures()
+        .batteries()
+        .unwrap()
+        .into_iter()
+        .map(|b| b.name)
+        .collect();
+    assert_eq!(names, ["BAT0", "BAT1", "BAT2", "BATT"]);
+}
+
+fn malformed() -> PowerSupplySource {
+    PowerSupplySource::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/malformed"
+    ))
+}
+
+#[test]
+fn non_numeric_value() {
+    match malformed().battery("BAT0") {
+        Err(BatteryError::Parse { key, value, .. }) => {
+            assert_eq!(key, "POWER_SUPPLY_ENERGY_NOW");
+            assert_eq!(value, "unknown");
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn line_without_separator() {
+    assert!(matches!(
+        malformed().battery("BAT1"),
+        Err(BatteryError::MalformedLine(line)) if line == "POWER_SUPPLY_PRESENT"
+    ));
+}
+
+#[test]
+fn non_utf8_value() {
+    assert!(matches!(
+        malformed().battery("BAT2"),
+        Err(BatteryError::UTF8(_))
+    ));
+}
+
+#[test]
+fn mixed_units() {
+    // a charge-based design capacity can't stand in for a missing energy one
+    assert!(matches!(
+        malformed().battery("BAT3"),
+        Err(BatteryError::MissingField(
+            "POWER_SUPPLY_ENERGY_FULL_DESIGN"
+        ))
+    ));
+}
+
+fn kernel_event(msg: &[u8]) -> KernelEvent {
+    KernelEvent::from_message(msg).unwrap()
+}
+
+#[test]
+fn watcher_filters_power_supply_events() {
+    let mut watcher = PowerSupplyWatcher::new(VecDeque::from([
+        kernel_event(b"change@/devices/pci0000:00/0000:00:02.0/drm/card0\0ACTION=change\0SUBSYSTEM=drm\0"),
+        kernel_event(b"change@/devices/LNXSYSTM:00/LNXSYBUS:00/ACPI0003:00/power_supply/AC\0ACTION=change\0SUBSYSTEM=power_supply\0POWER_SUPPLY_NAME=AC\0POWER_SUPPLY_TYPE=Mains\0POWER_SUPPLY_ONLINE=0\0"),
+        kernel_event(b"change@/devices/LNXSYSTM:00/LNXSYBUS:00/PNP0C0A:00/power_supply/BAT0\0ACTION=change\0SUBSYSTEM=power_supply\0POWER_SUPPLY_NAME=BAT0\0POWER_SUPPLY_STATUS=Discharging\0POWER_SUPPLY_ENERGY_FULL_DESIGN=57000000\0POWER_SUPPLY_ENERGY_FULL=51270000\0POWER_SUPPLY_ENERGY_NOW=33150000\0POWER_SUPPLY_POWER_NOW=7832000\0"),
+    ]));
+    let ac = watcher.recv(None).unwrap().unwrap();
+    assert_eq!(ac.name, "AC");
+    assert_eq!(ac.action, "change");
+    assert_eq!(ac.properties.get("POWER_SUPPLY_ONLINE"), Some("0"));
+    let bat = watcher.recv(None).unwrap().unwrap().battery().unwrap();
+    assert_eq!(bat.name, "BAT0");
+    assert_eq!(bat.status, BatteryStatus::Discharging);
+    assert_eq!(bat.power_now, Some(7832000));
+    assert_eq!(watcher.recv(Some(Duration::ZERO)).unwrap(), None);
+}
+
+#[test]
+fn watcher_skips_undecodable_events() {
+    let mut watcher = PowerSupplyWatcher::new(VecDeque::from([
+        KernelEvent::from_message(b"\xff@/devices/virtual/misc\0ACTION=change\0"),
+        KernelEvent::from_message(b"no header\0ACTION=change\0"),
+        KernelEvent::from_message(b"change@/devices/LNXSYSTM:00/LNXSYBUS:00/ACPI0003:00/power_supply/AC\0ACTION=change\0SUBSYSTEM=power_supply\0POWER_SUPPLY_NAME=AC\0POWER_SUPPLY_ONLINE=1\0"),
+    ]));
+    let ac = watcher.recv(None).unwrap().unwrap();
+    assert_eq!(ac.name, "AC");
+    assert_eq!(watcher.recv(Some(Duration::ZERO)).unwrap(), None);
+}
+
+fn sample(timestamp: u64, remaining: u32, status: BatteryStatus) -> Sample {
+    Sample {
+        timestamp,
+        remaining,
+        actual_max: 50_000_000,
+        status,
+        unit: CapacityUnit::Energy,
+    }
+}
+
+#[test]
+fn history_rates() {
+    let mut history = History::default();
+    // discharging at 10W for an hour, then charging at 20W for half an hour
+    history.push(sample(0, 40_000_000, BatteryStatus::Discharging));
+    history.push(sample(1800, 35_000_000, BatteryStatus::Discharging));
+    history.push(sample(3600, 30_000_000, BatteryStatus::Discharging));
+    history.push(sample(3600, 0, BatteryStatus::Unknown));
+    history.push(sample(3660, 30_000_000, BatteryStatus::Charging));
+    history.push(sample(5460, 40_000_000, BatteryStatus::Charging));
+    assert_eq!(history.samples.len(), 5);
+
+    let day = Duration::from_secs(24 * 60 * 60);
+    let discharge = history.average_discharge_rate(5460, day).unwrap();
+    assert!((discharge - 10_000_000.0).abs() < 1.0);
+    let charge = history.average_charge_rate(5460, day).unwrap();
+    assert!((charge - 20_000_000.0).abs() < 1.0);
+    assert_eq!(
+        history.average_discharge_rate(5460, Duration::from_secs(1800)),
+        None
+    );
+
+    history.prune(5460, Duration::from_secs(3600));
+    assert_eq!(history.samples.front().unwrap().timestamp, 3600);
+}
+
+#[test]
+fn combined_is_weighted_by_capacity() {
+    let source = fixtures();
+    let bat0 = source.battery("BAT0").unwrap();
+    let batt = source.battery("BATT").unwrap();
+    let combined = CombinedBattery::new([&bat0, &batt]).unwrap();
+    assert_eq!(combined.names, ["BAT0", "BATT"]);
+    assert_eq!(combined.remaining, 33150000 + 18488000);
+    assert_eq!(combined.actual_max, 51270000 + 23110000);
+    assert_eq!(combined.status, BatteryStatus::Discharging);
+    // (64.7% + 80%) / 2 would be 72.3%
+    assert!((combined.part_actual() - 0.6942).abs() < 0.001);
+    // only BAT0 reports a rate
+    assert!((combined.power_draw().unwrap() - 7.832).abs() < 0.001);
+    assert_eq!(format_duration(combined.time_to_empty().unwrap()), "6h 36m");
+    assert_eq!(CombinedBattery::new(&[]), None);
+}
+
+#[test]
+fn combined_status() {
+    let source = fixtures();
+    let mut full = source.battery("BAT2").unwrap();
+    full.status = BatteryStatus::Full;
+    let batt = source.battery("BATT").unwrap();
+    let status = |bats: &[&Battery]| CombinedBattery::new(bats.iter().copied()).unwrap().status;
+    assert_eq!(status(&[&full]), BatteryStatus::Full);
+    assert_eq!(status(&[&full, &batt]), BatteryStatus::NotCharging);
+    assert_eq!(
+        status(&[&full, &source.battery("BAT1").unwrap()]),
+        BatteryStatus::Charging
+    );
+    assert_eq!(
+        status(&[&batt, &source.battery("BAT2").unwrap()]),
+        BatteryStatus::Unknown
+    );
+}
+
+#[test]
+fn charge_thresholds() {
+    let source = fixtures();
+    assert_eq!(
+        source.charge_thresholds("BAT0").unwrap(),
+        ChargeThresholds {
+            start: Some(75),
+            end: Some(80)
+        }
+    );
+    assert_eq!(
+        source.charge_thresholds("BAT1").unwrap(),
+        ChargeThresholds::default()
+    );
+}
+
+#[test]
+fn set_charge_thresholds() {
+    let dir = tempfile::tempdir().unwrap();
+    let bat = dir.path().join("BAT0");
+    std::fs::create_dir(&bat).unwrap();
+    std::fs::write(bat.join("charge_control_start_threshold"), "75\n").unwrap();
+    std::fs::write(bat.join("charge_control_end_threshold"), "80\n").unwrap();
+    let source = PowerSupplySource::new(dir.path());
+
+    // raising start above the current end needs end written first
+    let new = source
+        .set_charge_thresholds("BAT0", Some(85), Some(90))
+        .unwrap();
+    assert_eq!(new, source.charge_thresholds("BAT0").unwrap());
+    assert_eq!(new.start, Some(85));
+    assert_eq!(new.end, Some(90));
+
+    assert_eq!(
+        source
+            .set_charge_thresholds("BAT0", None, Some(60))
+            .unwrap_err()
+            .to_string(),
+        "invalid charge thresholds: start threshold 85% must be below end threshold 60%"
+    );
+    assert!(matches!(
+        source.set_charge_thresholds("BAT0", Some(40), Some(101)),
+        Err(BatteryError::InvalidThreshold(_))
+    ));
+    assert_eq!(source.charge_thresholds("BAT0").unwrap(), new);
+
+    std::fs::create_dir(dir.path().join("BAT1")).unwrap();
+    assert!(matches!(
+        source.set_charge_thresholds("BAT1", None, Some(80)),
+        Err(BatteryError::Unsupported(name)) if name == "BAT1"
+    ));
+}
+
+#[test]
+fn full_state_respects_charge_limit() {
+    let mut bat = fixtures().battery("BAT1").unwrap();
+    bat.remaining = bat.actual_max * 77 / 100;
+    assert_eq!(bat.full_state(0.95), FullState::NotFull);
+    bat.status = BatteryStatus::NotCharging;
+    assert_eq!(bat.full_state(0.95), FullState::Full);
+
+    bat.charge_limit = Some(80);
+    assert_eq!(bat.full_state(0.95), FullState::HeldAtLimit);
+    bat.status = BatteryStatus::Charging;
+    // 77% is within 95% of the 80% limit
+    assert_eq!(bat.full_state(0.95), FullState::HeldAtLimit);
+    assert_eq!(bat.full_state(0.99), FullState::NotFull);
+    bat.status = BatteryStatus::Full;
+    assert_eq!(bat.full_state(0.95), FullState::Full);
+    bat.status = BatteryStatus::Discharging;
+    assert!(!bat.is_full(0.95));
+
+    bat.charge_limit = Some(100);
+    bat.status = BatteryStatus::Charging;
+    assert_eq!(bat.full_state(0.95), FullState::NotFull);
+}
+
+#[test]
+fn capacity_conversion() {
+    let bat1 = fixtures().battery("BAT1").unwrap();
+    assert_eq!(bat1.remaining_capacity(), Capacity::AmpHours(2.14));
+    assert_eq!(bat1.remaining_capacity().to_string(), "2.14Ah");
+    // converted at the design voltage, not the present one
+    assert_eq!(bat1.nominal_voltage(), Some(11.4));
+    let wh = bat1.remaining_capacity().watt_hours(bat1.nominal_voltage());
+    assert!((wh.unwrap() - 24.396).abs() < 0.001);
+    assert_eq!(bat1.remaining_capacity().watt_hours(None), None);
+    assert_eq!(
+        Capacity::WattHours(22.8).to_unit(CapacityUnit::Charge, Some(11.4)),
+        Some(Capacity::AmpHours(2.0))
+    );
+    assert_eq!(Capacity::WattHours(22.8).amp_hours(None), None);
+    assert_eq!(Capacity::WattHours(22.8).watt_hours(None), Some(22.8));
+}
+
+#[test]
+fn combined_converts_units() {
+    let source = fixtures();
+    let bat0 = source.battery("BAT0").unwrap();
+    let mut bat1 = source.battery("BAT1").unwrap();
+    let combined = CombinedBattery::new([&bat0, &bat1]).unwrap();
+    assert_eq!(combined.unit, CapacityUnit::Energy);
+    assert_eq!(combined.remaining, 33150000 + 24396000);
+    assert_eq!(combined.actual_max, 51270000 + 45189600);
+    // BAT1 charges faster than BAT0 drains, so the total isn't dropping
+    assert_eq!(combined.status, BatteryStatus::Discharging);
+    assert_eq!(combined.time_to_empty(), None);
+    bat1.current_now = Some(300000);
+    let combined = CombinedBattery::new([&bat0, &bat1]).unwrap();
+    assert_eq!(format_duration(combined.time_to_empty().unwrap()), "13h 3m");
+    // a net rate that nearly cancels out gives no estimate rather than a panic
+    let mut bat0_slow = bat0.clone();
+    bat0_slow.power_now = Some(1_003_000);
+    let mut bat1_slow = bat1.clone();
+    bat1_slow.current_now = Some(100_000);
+    bat1_slow.voltage_min_design = Some(10_030_000);
+    let combined = CombinedBattery::new([&bat0_slow, &bat1_slow]).unwrap();
+    assert_eq!(combined.time_to_empty(), None);
+    assert_eq!(combined.time_to_full(), None);
+
+    // without a voltage for BAT1, everything is summed in Ah instead
+    bat1.voltage_now = None;
+    bat1.voltage_min_design = None;
+    let combined = CombinedBattery::new([&bat0, &bat1]).unwrap();
+    assert_eq!(combined.unit, CapacityUnit::Charge);
+    assert_eq!(combined.remaining, 2140000 + 2147021);
+
+    // and batteries that can't be converted either way are left out
+    let mut bat0 = bat0;
+    bat0.voltage_now = None;
+    bat0.voltage_min_design = None;
+    let combined = CombinedBattery::new([&bat0, &bat1]).unwrap();
+    assert_eq!(combined.names, ["BAT1"]);
+    assert_eq!(combined.remaining, 2140000);
+}
+
+#[test]
+fn peripherals() {
+    let source = fixtures();
+    assert_eq!(
+        source.peripheral_names().unwrap(),
+        ["hid-dc:2c:26:0a:1b:2c-battery", "hidpp_battery_0"]
+    );
+    let mouse = source.peripheral("hidpp_battery_0").unwrap();
+    assert_eq!(
+        mouse,
+        Peripheral {
+            name: "hidpp_battery_0".to_owned(),
+            status: BatteryStatus::Discharging,
+            capacity: None,
+            capacity_level: Some(CapacityLevel::Low),
+            manufacturer: Some("Logitech".to_owned()),
+            model_name: Some("MX Master 3".to_owned()),
+            serial_number: Some("4082-a1-b2-c3-d4".to_owned()),
+        }
+    );
+    assert_eq!(mouse.display_name(), "Logitech MX Master 3");
+    assert!(mouse.is_low(5.0));
+    // peripherals have no energy or charge values to read as a battery
+    assert!(matches!(
+        source.battery("hidpp_battery_0"),
+        Err(BatteryError::MissingField(_))
+    ));
+
+    let keyboard = source.peripheral("hid-dc:2c:26:0a:1b:2c-battery").unwrap();
+    assert_eq!(keyboard.capacity, Some(15));
+    assert_eq!(keyboard.display_name(), "Keychron K2");
+    assert!(keyboard.is_low(20.0));
+    assert!(!keyboard.is_low(10.0));
+}
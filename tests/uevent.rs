@@ -0,0 +1,67 @@
+use script_lib::uevent::{KernelEvent, UEvent, UEventError};
+use std::path::Path;
+
+#[test]
+fn from_reader() {
+    let uevent = UEvent::from_reader(
+        &b"DEVTYPE=backlight\n\nMODALIAS=acpi:LNXVIDEO:\nEXTRA= a=b \nEMPTY= \t\n"[..],
+    )
+    .unwrap();
+    assert_eq!(uevent.len(), 4);
+    assert_eq!(uevent.get("DEVTYPE"), Some("backlight"));
+    assert_eq!(uevent.get("EXTRA"), Some("a=b"));
+    assert_eq!(uevent.get("EMPTY"), Some(""));
+    assert_eq!(uevent.get("MISSING"), None);
+}
+
+#[test]
+fn parse_typed() {
+    let uevent = UEvent::from_device(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/power_supply/BAT0"
+    )))
+    .unwrap();
+    assert_eq!(
+        uevent.parse::<u32>("POWER_SUPPLY_CYCLE_COUNT").unwrap(),
+        Some(312)
+    );
+    assert_eq!(
+        uevent.parse::<u32>("POWER_SUPPLY_CHARGE_NOW").unwrap(),
+        None
+    );
+    assert!(matches!(
+        uevent.parse::<u32>("POWER_SUPPLY_MODEL_NAME"),
+        Err(UEventError::Parse { .. })
+    ));
+}
+
+#[test]
+fn malformed_line() {
+    assert!(matches!(
+        UEvent::from_reader(&b"A=1\nB\n"[..]),
+        Err(UEventError::MalformedLine(line)) if line == "B"
+    ));
+}
+
+#[test]
+fn kernel_event_from_message() {
+    let event = KernelEvent::from_message(
+        b"change@/devices/LNXSYSTM:00/LNXSYBUS:00/PNP0C0A:00/power_supply/BAT0\0ACTION=change\0SUBSYSTEM=power_supply\0POWER_SUPPLY_NAME=BAT0\0SEQNUM=4312\0",
+    )
+    .unwrap();
+    assert_eq!(event.action, "change");
+    assert_eq!(
+        event.devpath,
+        "/devices/LNXSYSTM:00/LNXSYBUS:00/PNP0C0A:00/power_supply/BAT0"
+    );
+    assert_eq!(event.subsystem(), Some("power_supply"));
+    assert_eq!(event.properties.get("SEQNUM"), Some("4312"));
+}
+
+#[test]
+fn kernel_event_without_header() {
+    assert!(matches!(
+        KernelEvent::from_message(b"libudev\0"),
+        Err(UEventError::MalformedLine(_))
+    ));
+}
@@ -0,0 +1,186 @@
+use dbus::Path;
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+use script_lib::{
+    battery::{
+        upower::{UPowerSource, BUS_NAME, UPOWER_PATH},
+        BatteryBackend, BatteryStatus, CapacityLevel, CapacityUnit,
+    },
+    dbus_mock::Bus,
+};
+
+#[derive(Clone, Default)]
+struct Device {
+    native_path: String,
+    kind: u32,
+    power_supply: bool,
+    online: bool,
+    state: u32,
+    energy: f64,
+    energy_full: f64,
+    energy_full_design: f64,
+    energy_rate: f64,
+    voltage: f64,
+    technology: u32,
+    charge_cycles: i32,
+    percentage: f64,
+    battery_level: u32,
+    vendor: String,
+    model: String,
+    serial: String,
+}
+
+/// Serves a fake `org.freedesktop.UPower` with the given devices on its own thread.
+fn serve(bus: &Bus, devices: Vec<Device>) {
+    let mut cr = Crossroads::new();
+    let paths: Vec<Path<'static>> = (0..devices.len())
+        .map(|i| Path::from(format!("{UPOWER_PATH}/devices/dev{i}")))
+        .collect();
+    let upower = cr.register("org.freedesktop.UPower", {
+        let paths = paths.clone();
+        move |b: &mut IfaceBuilder<()>| {
+            let paths = paths.clone();
+            b.method("EnumerateDevices", (), ("devices",), move |_, _, ()| {
+                Ok((paths.clone(),))
+            });
+        }
+    });
+    cr.insert(UPOWER_PATH, &[upower], ());
+    let device = cr.register(
+        "org.freedesktop.UPower.Device",
+        |b: &mut IfaceBuilder<Device>| {
+            b.property("NativePath")
+                .get(|_, d| Ok(d.native_path.clone()));
+            b.property("Type").get(|_, d| Ok(d.kind));
+            b.property("PowerSupply").get(|_, d| Ok(d.power_supply));
+            b.property("Online").get(|_, d| Ok(d.online));
+            b.property("State").get(|_, d| Ok(d.state));
+            b.property("Energy").get(|_, d| Ok(d.energy));
+            b.property("EnergyFull").get(|_, d| Ok(d.energy_full));
+            b.property("EnergyFullDesign")
+                .get(|_, d| Ok(d.energy_full_design));
+            b.property("EnergyRate").get(|_, d| Ok(d.energy_rate));
+            b.property("Voltage").get(|_, d| Ok(d.voltage));
+            b.property("Technology").get(|_, d| Ok(d.technology));
+            b.property("ChargeCycles").get(|_, d| Ok(d.charge_cycles));
+            b.property("Percentage").get(|_, d| Ok(d.percentage));
+            b.property("BatteryLevel").get(|_, d| Ok(d.battery_level));
+            b.property("Vendor").get(|_, d| Ok(d.vendor.clone()));
+            b.property("Model").get(|_, d| Ok(d.model.clone()));
+            b.property("Serial").get(|_, d| Ok(d.serial.clone()));
+        },
+    );
+    for (path, dev) in paths.into_iter().zip(devices) {
+        cr.insert(path, &[device], dev);
+    }
+    bus.serve(BUS_NAME, cr).unwrap();
+}
+
+fn laptop() -> Vec<Device> {
+    vec![
+        Device {
+            native_path: "BAT0".to_owned(),
+            kind: 2,
+            power_supply: true,
+            state: 2,
+            energy: 33.15,
+            energy_full: 51.27,
+            energy_full_design: 57.0,
+            energy_rate: 7.832,
+            voltage: 16.213,
+            technology: 2,
+            charge_cycles: 312,
+            vendor: "SMP".to_owned(),
+            model: "5B10W13975".to_owned(),
+            serial: "1234".to_owned(),
+            ..Default::default()
+        },
+        Device {
+            native_path: "/sys/devices/platform/AC".to_owned(),
+            kind: 1,
+            power_supply: true,
+            online: true,
+            ..Default::default()
+        },
+        Device {
+            native_path: "hidpp_battery_0".to_owned(),
+            kind: 5,
+            state: 2,
+            energy: 1.0,
+            energy_full: 2.0,
+            energy_full_design: 2.0,
+            charge_cycles: -1,
+            percentage: 10.0,
+            battery_level: 3,
+            vendor: "Logitech".to_owned(),
+            model: "MX Master 3".to_owned(),
+            ..Default::default()
+        },
+        Device {
+            native_path: "/sys/devices/virtual/hid-dc:2c:26:0a:1b:2c-battery".to_owned(),
+            kind: 6,
+            state: 2,
+            percentage: 55.0,
+            battery_level: 1,
+            model: "Keychron K2".to_owned(),
+            ..Default::default()
+        },
+    ]
+}
+
+#[test]
+fn reads_batteries_from_upower() {
+    let bus = Bus::start().expect("failed to start dbus-daemon");
+    serve(&bus, laptop());
+    let source = UPowerSource::with_connection(bus.connect().unwrap());
+
+    assert_eq!(source.battery_names().unwrap(), ["BAT0"]);
+    assert!(source.is_plugged_in().unwrap());
+
+    let bat = source.battery("BAT0").unwrap();
+    assert_eq!(bat.status, BatteryStatus::Discharging);
+    assert_eq!(bat.unit, CapacityUnit::Energy);
+    assert_eq!(bat.remaining, 33150000);
+    assert_eq!(bat.actual_max, 51270000);
+    assert_eq!(bat.factory_max, 57000000);
+    assert_eq!(bat.power_now, Some(7832000));
+    assert_eq!(bat.cycle_count, Some(312));
+    assert_eq!(bat.technology.as_deref(), Some("Li-poly"));
+    assert_eq!(bat.manufacturer.as_deref(), Some("SMP"));
+    assert_eq!(bat.charge_limit, None);
+    assert_eq!(source.batteries().unwrap().into_iter().next().unwrap(), bat);
+
+    let mouse = source.battery("hidpp_battery_0").unwrap();
+    assert_eq!(mouse.cycle_count, None);
+    assert_eq!(mouse.power_now, None);
+    assert!(source.battery("BAT1").is_err());
+}
+
+#[test]
+fn reads_peripherals_from_upower() {
+    let bus = Bus::start().expect("failed to start dbus-daemon");
+    serve(&bus, laptop());
+    let source = UPowerSource::with_connection(bus.connect().unwrap());
+
+    let peripherals = source.peripherals().unwrap();
+    assert_eq!(peripherals.len(), 2);
+    let keyboard = &peripherals[0];
+    assert_eq!(keyboard.name, "hid-dc:2c:26:0a:1b:2c-battery");
+    assert_eq!(keyboard.capacity, Some(55));
+    assert_eq!(keyboard.capacity_level, None);
+    let mouse = &peripherals[1];
+    assert_eq!(mouse.display_name(), "Logitech MX Master 3");
+    assert_eq!(mouse.status, BatteryStatus::Discharging);
+    // the percentage UPower derives from a coarse level isn't a real reading
+    assert_eq!(mouse.capacity, None);
+    assert_eq!(mouse.capacity_level, Some(CapacityLevel::Low));
+}
+
+#[test]
+fn unplugged_without_line_power() {
+    let bus = Bus::start().expect("failed to start dbus-daemon");
+    let mut devices = laptop();
+    devices[1].online = false;
+    serve(&bus, devices);
+    let source = UPowerSource::with_connection(bus.connect().unwrap());
+    assert!(!source.is_plugged_in().unwrap());
+}